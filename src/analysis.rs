@@ -0,0 +1,327 @@
+use bevy::prelude::*;
+
+use crate::{
+    board::{BOARD_SIZE, OFFSET, TILE_SIZE, get_world_position},
+    chess::{parse_uci_move, uci_line_to_san},
+    components::{EvalBar, EvalBarFill, Piece, PieceColor, Square},
+    resources::{BoardOrientation, GameState, MoveHistory, Players},
+    states::InGame,
+    uci::{Score, SearchKind, UciEngine, current_fen},
+};
+
+pub const EVAL_BAR_WIDTH: f32 = 24.0;
+
+pub struct AnalysisPlugin;
+
+impl Plugin for AnalysisPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnalysisMode>()
+            .init_resource::<Evaluation>()
+            .add_systems(Startup, setup_arrow_gizmos)
+            .add_systems(OnEnter(InGame), spawn_eval_bar)
+            .add_systems(OnExit(InGame), stop_analysis)
+            .add_systems(
+                Update,
+                (
+                    toggle_analysis_system,
+                    run_analysis_system.after(toggle_analysis_system),
+                    update_evaluation_system.after(run_analysis_system),
+                    update_eval_bar_system.after(update_evaluation_system),
+                    draw_best_move_arrow_system.after(update_evaluation_system),
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct AnalysisMode {
+    pub enabled: bool,
+}
+
+// What the engine currently thinks of the position on the board.
+#[derive(Resource, Default)]
+pub struct Evaluation {
+    // The position this evaluation belongs to.
+    pub fen: String,
+    // From White's point of view.
+    pub score: Option<Score>,
+    pub depth: Option<u32>,
+    pub best_move: Option<((u8, u8), (u8, u8))>,
+    // The principal variation in UCI notation, as sent by the engine.
+    pub pv: Vec<String>,
+    // The principal variation in numbered SAN, e.g. "12... Nf6 13. Bg5".
+    pub line: String,
+}
+
+/// Numbers a line of SAN moves starting at the given move, e.g. "12... Nf6 13. Bg5".
+pub fn numbered_line(fullmove_number: u32, turn: PieceColor, sans: &[String]) -> String {
+    let mut line = String::new();
+    let mut number = fullmove_number;
+    let mut turn = turn;
+
+    for (i, san) in sans.iter().enumerate() {
+        match turn {
+            PieceColor::White => {
+                line.push_str(&format!("{number}. "));
+                turn = PieceColor::Black;
+            }
+            PieceColor::Black => {
+                if i == 0 {
+                    line.push_str(&format!("{number}... "));
+                }
+                number += 1;
+                turn = PieceColor::White;
+            }
+        }
+        line.push_str(san);
+        line.push(' ');
+    }
+
+    line.trim_end().to_string()
+}
+
+/// Share of the evaluation bar that belongs to White, between 0 and 1.
+pub fn white_share(score: Option<Score>) -> f32 {
+    match score {
+        Some(Score::Centipawns(cp)) => 1.0 / (1.0 + 10f32.powf(-(cp as f32) / 400.0)),
+        Some(Score::Mate(moves)) if moves > 0 => 1.0,
+        Some(Score::Mate(_)) => 0.0,
+        None => 0.5,
+    }
+}
+
+fn toggle_analysis_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut analysis: ResMut<AnalysisMode>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyA) {
+        analysis.enabled = !analysis.enabled;
+    }
+}
+
+// Keeps the engine analysing the position on the board. Whenever a move is made (or the position
+// changes in any other way) the old search is stopped and a new one is started once it has ended.
+fn run_analysis_system(
+    engine: Option<ResMut<UciEngine>>,
+    analysis: Res<AnalysisMode>,
+    players: Res<Players>,
+    piece_query: Query<(&Piece, &Square)>,
+    game_state: Res<GameState>,
+) {
+    let Some(mut engine) = engine else {
+        return;
+    };
+    if !engine.ready {
+        return;
+    }
+
+    // When it is the engine's own turn, it is busy playing.
+    let wanted = analysis.enabled && players.is_human(game_state.turn);
+    let fen = current_fen(&piece_query, &game_state);
+
+    match &engine.searching {
+        Some(search) if search.kind == SearchKind::Analysis => {
+            if !wanted || search.fen != fen {
+                engine.stop();
+            }
+        }
+        Some(_) => {}
+        None => {
+            if wanted {
+                engine.go(fen, SearchKind::Analysis, "infinite");
+            }
+        }
+    }
+}
+
+// Nothing is left to analyse once the game is gone.
+fn stop_analysis(engine: Option<ResMut<UciEngine>>) {
+    if let Some(mut engine) = engine
+        && engine
+            .searching
+            .as_ref()
+            .is_some_and(|search| search.kind == SearchKind::Analysis)
+    {
+        engine.stop();
+    }
+}
+
+fn update_evaluation_system(
+    engine: Option<Res<UciEngine>>,
+    analysis: Res<AnalysisMode>,
+    piece_query: Query<(&Piece, &Square)>,
+    game_state: Res<GameState>,
+    mut evaluation: ResMut<Evaluation>,
+) {
+    if !analysis.enabled {
+        if !evaluation.fen.is_empty() {
+            *evaluation = Evaluation::default();
+        }
+        return;
+    }
+
+    let fen = current_fen(&piece_query, &game_state);
+    if evaluation.fen != fen {
+        *evaluation = Evaluation { fen, ..default() };
+    }
+
+    let Some(engine) = engine else {
+        return;
+    };
+    let Some(search) = &engine.searching else {
+        return;
+    };
+    let Some(info) = &engine.last_info else {
+        return;
+    };
+    if search.kind != SearchKind::Analysis || search.fen != evaluation.fen {
+        return;
+    }
+
+    // Engines score from the side to move's point of view.
+    let score = info.score.map(|score| match (game_state.turn, score) {
+        (PieceColor::White, _) => score,
+        (PieceColor::Black, Score::Centipawns(cp)) => Score::Centipawns(-cp),
+        (PieceColor::Black, Score::Mate(moves)) => Score::Mate(-moves),
+    });
+    // A deeper search often keeps the same line, so the score and depth are updated on their own.
+    if evaluation.score != score || evaluation.depth != info.depth {
+        evaluation.score = score;
+        evaluation.depth = info.depth;
+    }
+    if info.pv == evaluation.pv {
+        return;
+    }
+
+    evaluation.best_move = info
+        .pv
+        .first()
+        .and_then(|uci| parse_uci_move(uci))
+        .map(|(start, end, _)| (start, end));
+
+    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+    let sans = uci_line_to_san(
+        &board,
+        game_state.turn,
+        game_state.en_passant_target,
+        &info.pv,
+    );
+    evaluation.line = numbered_line(game_state.fullmove_number, game_state.turn, &sans);
+    evaluation.pv = info.pv.clone();
+}
+
+fn spawn_eval_bar(mut commands: Commands) {
+    let x = -OFFSET - EVAL_BAR_WIDTH;
+
+    commands.spawn((
+        Sprite {
+            color: Color::srgb(0.15, 0.15, 0.15),
+            custom_size: Some(Vec2::new(EVAL_BAR_WIDTH, BOARD_SIZE)),
+            ..default()
+        },
+        Transform::from_xyz(x, 0.0, 0.0),
+        Visibility::Hidden,
+        EvalBar,
+        DespawnOnExit(InGame),
+    ));
+
+    commands.spawn((
+        Sprite {
+            color: Color::srgb(0.95, 0.95, 0.95),
+            custom_size: Some(Vec2::new(EVAL_BAR_WIDTH, BOARD_SIZE / 2.0)),
+            ..default()
+        },
+        Transform::from_xyz(x, -BOARD_SIZE / 4.0, 0.1),
+        Visibility::Hidden,
+        EvalBarFill,
+        DespawnOnExit(InGame),
+    ));
+}
+
+fn update_eval_bar_system(
+    analysis: Res<AnalysisMode>,
+    evaluation: Res<Evaluation>,
+    orientation: Res<BoardOrientation>,
+    mut bar_query: Query<&mut Visibility, (With<EvalBar>, Without<EvalBarFill>)>,
+    mut fill_query: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<EvalBarFill>>,
+) {
+    let visibility = if analysis.enabled {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+
+    for mut bar_visibility in bar_query.iter_mut() {
+        bar_visibility.set_if_neq(visibility);
+    }
+
+    for (mut sprite, mut transform, mut fill_visibility) in fill_query.iter_mut() {
+        fill_visibility.set_if_neq(visibility);
+
+        // The fill is anchored to White's side of the board.
+        let height = BOARD_SIZE * white_share(evaluation.score);
+        sprite.custom_size = Some(Vec2::new(EVAL_BAR_WIDTH, height));
+        transform.translation.y = if orientation.is_flipped() {
+            OFFSET - height / 2.0
+        } else {
+            -OFFSET + height / 2.0
+        };
+    }
+}
+
+fn setup_arrow_gizmos(mut config_store: ResMut<GizmoConfigStore>) {
+    let (config, _) = config_store.config_mut::<DefaultGizmoConfigGroup>();
+    config.line.width = 8.0;
+}
+
+fn draw_best_move_arrow_system(
+    mut gizmos: Gizmos,
+    evaluation: Res<Evaluation>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+) {
+    // The arrow belongs to the live position.
+    if history.is_browsing() {
+        return;
+    }
+    let Some((start, end)) = evaluation.best_move else {
+        return;
+    };
+
+    gizmos
+        .arrow_2d(
+            get_world_position(start.0 as usize, start.1 as usize, 0.0, &orientation).truncate(),
+            get_world_position(end.0 as usize, end.1 as usize, 0.0, &orientation).truncate(),
+            Color::srgba(0.1, 0.45, 0.9, 0.8),
+        )
+        .with_tip_length(TILE_SIZE * 0.3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_lines_from_either_side() {
+        let sans = ["Nf6", "Bg5", "e6"].map(String::from);
+        assert_eq!(
+            numbered_line(12, PieceColor::Black, &sans),
+            "12... Nf6 13. Bg5 e6"
+        );
+        assert_eq!(
+            numbered_line(1, PieceColor::White, &sans[..2]),
+            "1. Nf6 Bg5"
+        );
+        assert_eq!(numbered_line(1, PieceColor::White, &[]), "");
+    }
+
+    #[test]
+    fn splits_the_eval_bar() {
+        assert_eq!(white_share(None), 0.5);
+        assert_eq!(white_share(Some(Score::Centipawns(0))), 0.5);
+        assert!(white_share(Some(Score::Centipawns(300))) > 0.5);
+        assert_eq!(white_share(Some(Score::Mate(2))), 1.0);
+        assert_eq!(white_share(Some(Score::Mate(-2))), 0.0);
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+
+use crate::{
+    board::{PIECE_SCALE, get_world_position},
+    components::{CaptureAnimation, Dragging, MoveAnimation, Piece, Square},
+    resources::{BoardOrientation, DisplayOptions},
+};
+
+pub const MOVE_ANIMATION_SECONDS: f32 = 0.2;
+// Moving pieces are drawn above the resting ones.
+const MOVING_Z: f32 = 2.0;
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                piece_movement_system,
+                animate_movement_system.after(piece_movement_system),
+                animate_capture_system,
+            ),
+        );
+    }
+}
+
+// Starts a move animation whenever a piece's square changes. The square itself changes at once,
+// so the board logic and input never wait for the animation. With animations off, the piece is
+// put on its square right away.
+#[allow(clippy::type_complexity)]
+fn piece_movement_system(
+    mut commands: Commands,
+    mut movement_query: Query<(Entity, &Square, &mut Transform), (With<Piece>, Changed<Square>)>,
+    orientation: Res<BoardOrientation>,
+    display_options: Res<DisplayOptions>,
+) {
+    for (entity, square, mut transform) in movement_query.iter_mut() {
+        let to = get_world_position(square.x as usize, square.y as usize, 1.0, &orientation);
+        if transform.translation.truncate() == to.truncate() {
+            continue;
+        }
+        if display_options.animation_speed <= 0.0 {
+            transform.translation = to;
+            continue;
+        }
+
+        commands.entity(entity).insert(MoveAnimation {
+            from: transform.translation,
+            to,
+            timer: Timer::from_seconds(
+                MOVE_ANIMATION_SECONDS / display_options.animation_speed,
+                TimerMode::Once,
+            ),
+        });
+    }
+}
+
+fn animate_movement_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut animation_query: Query<(Entity, &mut MoveAnimation, &mut Transform), Without<Dragging>>,
+) {
+    for (entity, mut animation, mut transform) in animation_query.iter_mut() {
+        animation.timer.tick(time.delta());
+
+        if animation.timer.is_finished() {
+            transform.translation = animation.to;
+            commands.entity(entity).remove::<MoveAnimation>();
+            continue;
+        }
+
+        let progress = EaseFunction::CubicInOut.sample_clamped(animation.timer.fraction());
+        transform.translation = animation.from.lerp(animation.to, progress).with_z(MOVING_Z);
+    }
+}
+
+fn animate_capture_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut capture_query: Query<(Entity, &mut CaptureAnimation, &mut Sprite, &mut Transform)>,
+) {
+    for (entity, mut animation, mut sprite, mut transform) in capture_query.iter_mut() {
+        animation.timer.tick(time.delta());
+
+        if animation.timer.is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let progress = animation.timer.fraction();
+        sprite.color.set_alpha(1.0 - progress);
+        transform.scale = Vec3::splat(PIECE_SCALE * (1.0 - 0.5 * progress));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::components::{PieceColor, PieceKind};
+
+    #[test]
+    fn pieces_glide_to_their_new_square() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<BoardOrientation>()
+            .init_resource::<DisplayOptions>()
+            .add_plugins(AnimationPlugin);
+        let piece = app
+            .world_mut()
+            .spawn((
+                Piece {
+                    color: PieceColor::White,
+                    kind: PieceKind::Pawn,
+                    has_moved: false,
+                    start_pos: (4, 1),
+                },
+                Square { x: 4, y: 1 },
+                Transform::from_translation(get_world_position(
+                    4,
+                    1,
+                    1.0,
+                    &BoardOrientation::default(),
+                )),
+            ))
+            .id();
+        app.update();
+        assert!(app.world().get::<MoveAnimation>(piece).is_none());
+
+        app.world_mut().get_mut::<Square>(piece).unwrap().y = 3;
+        app.update();
+        assert!(app.world().get::<MoveAnimation>(piece).is_some());
+
+        let time_step = Duration::from_secs_f32(MOVE_ANIMATION_SECONDS / 2.0);
+        app.world_mut().resource_mut::<Time>().advance_by(time_step);
+        app.update();
+        let halfway = app.world().get::<Transform>(piece).unwrap().translation;
+        assert!(halfway.y > get_world_position(4, 1, 1.0, &BoardOrientation::default()).y);
+        assert!(halfway.y < get_world_position(4, 3, 1.0, &BoardOrientation::default()).y);
+
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(time_step * 2);
+        app.update();
+        let end = app.world().get::<Transform>(piece).unwrap().translation;
+        assert_eq!(
+            end,
+            get_world_position(4, 3, 1.0, &BoardOrientation::default())
+        );
+        assert!(app.world().get::<MoveAnimation>(piece).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+// Arrows and marked squares drawn with the right mouse button. Dragging from one square to another
+// draws an arrow, a click marks the square, and doing it again takes it away. Shift or Ctrl draws
+// in red, Alt in blue, Shift+Alt in yellow and no modifier in green. They belong to the position on
+// display and are saved with the game; a left click on the board clears them.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    board::{TILE_SIZE, get_board_square, get_world_position},
+    resources::{BoardOrientation, MarkColor, MoveHistory},
+    states::InGame,
+    systems::cursor_world_position,
+};
+
+pub struct AnnotationPlugin;
+
+impl Plugin for AnnotationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RightDrag>().add_systems(
+            Update,
+            (
+                annotation_input_system,
+                draw_annotations_system.after(annotation_input_system),
+            )
+                .run_if(in_state(InGame)),
+        );
+    }
+}
+
+fn mark_color(color: MarkColor) -> Color {
+    match color {
+        MarkColor::Green => Color::srgba(0.08, 0.47, 0.11, 0.8),
+        MarkColor::Red => Color::srgba(0.53, 0.0, 0.0, 0.8),
+        MarkColor::Yellow => Color::srgba(0.9, 0.68, 0.0, 0.8),
+        MarkColor::Blue => Color::srgba(0.0, 0.19, 0.53, 0.8),
+    }
+}
+
+fn modifier_color(keys: &ButtonInput<KeyCode>) -> MarkColor {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let alt = keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+    match (shift || ctrl, alt) {
+        (false, false) => MarkColor::Green,
+        (true, false) => MarkColor::Red,
+        (false, true) => MarkColor::Blue,
+        (true, true) => MarkColor::Yellow,
+    }
+}
+
+// The square the right button went down on, while it is held.
+#[derive(Resource, Default)]
+struct RightDrag {
+    start: Option<(u8, u8)>,
+}
+
+fn annotation_input_system(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    orientation: Res<BoardOrientation>,
+    mut history: ResMut<MoveHistory>,
+    mut drag: ResMut<RightDrag>,
+) {
+    let square = cursor_world_position(&window_query, &camera_query)
+        .and_then(|position| get_board_square(position, &orientation));
+    let ply = history.shown_ply();
+
+    if mouse_input.just_pressed(MouseButton::Left) && square.is_some() {
+        if history.annotations(ply).is_some_and(|a| !a.is_empty())
+            && let Some(annotations) = history.annotations_mut(ply)
+        {
+            *annotations = default();
+        }
+        drag.start = None;
+    }
+
+    if mouse_input.just_pressed(MouseButton::Right) {
+        drag.start = square;
+    }
+    if mouse_input.just_released(MouseButton::Right)
+        && let Some(start) = drag.start.take()
+        && let Some(end) = square
+        && let Some(annotations) = history.annotations_mut(ply)
+    {
+        let color = modifier_color(&keys);
+        if start == end {
+            annotations.toggle_square(start, color);
+        } else {
+            annotations.toggle_arrow(start, end, color);
+        }
+    }
+}
+
+fn draw_arrow(gizmos: &mut Gizmos, start: Vec2, end: Vec2, color: Color) {
+    // Starts at the edge of the first square and ends a little short of the center of the last.
+    let direction = (end - start).normalize_or_zero();
+    gizmos
+        .arrow_2d(
+            start + direction * TILE_SIZE * 0.35,
+            end - direction * TILE_SIZE * 0.1,
+            color,
+        )
+        .with_tip_length(TILE_SIZE * 0.3);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_annotations_system(
+    mut gizmos: Gizmos,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    drag: Res<RightDrag>,
+) {
+    let center = |(x, y): (u8, u8)| {
+        get_world_position(x as usize, y as usize, 0.0, &orientation).truncate()
+    };
+
+    if let Some(annotations) = history.annotations(history.shown_ply()) {
+        for (square, color) in &annotations.squares {
+            gizmos.circle_2d(
+                Isometry2d::from_translation(center(*square)),
+                TILE_SIZE * 0.45,
+                mark_color(*color),
+            );
+        }
+        for (start, end, color) in &annotations.arrows {
+            draw_arrow(
+                &mut gizmos,
+                center(*start),
+                center(*end),
+                mark_color(*color),
+            );
+        }
+    }
+
+    // The arrow being drawn follows the cursor from square to square.
+    if mouse_input.pressed(MouseButton::Right)
+        && let Some(start) = drag.start
+        && let Some(end) = cursor_world_position(&window_query, &camera_query)
+            .and_then(|position| get_board_square(position, &orientation))
+        && end != start
+    {
+        let color = mark_color(modifier_color(&keys));
+        draw_arrow(&mut gizmos, center(start), center(end), color);
+    }
+}
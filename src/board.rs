@@ -1,16 +1,43 @@
-use crate::components::*;
-use bevy::prelude::*;
+use crate::{
+    components::*,
+    resources::{BoardOrientation, DisplayOptions, GameState, PlayerKind, Players},
+    states::InGame,
+    theme::Theme,
+    ui::side_panel_width,
+};
+use bevy::{prelude::*, sprite::Anchor, window::PrimaryWindow};
 
 // Constants for positioning
 pub const TILE_SIZE: f32 = 100.0;
 pub const BOARD_SIZE: f32 = TILE_SIZE * 8.0;
 pub const OFFSET: f32 = BOARD_SIZE / 2.0;
+pub const PIECE_SCALE: f32 = 0.8;
+// The board plus room around it for the clocks, captured pieces and the evaluation bar.
+pub const VIEW_SIZE: f32 = BOARD_SIZE + TILE_SIZE;
 
 pub struct BoardPlugin;
 
 impl Plugin for BoardPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (setup_camera, spawn_board, spawn_pieces));
+        app.add_systems(Startup, setup_camera)
+            .add_systems(OnEnter(InGame), spawn_board)
+            .add_systems(Update, fit_camera_system)
+            .add_systems(
+                Update,
+                (
+                    flip_board_system,
+                    auto_flip_system,
+                    toggle_coordinates_system,
+                    spawn_coordinates_system
+                        .after(flip_board_system)
+                        .after(auto_flip_system)
+                        .after(toggle_coordinates_system),
+                    orient_board_system
+                        .after(flip_board_system)
+                        .after(auto_flip_system),
+                )
+                    .run_if(in_state(InGame)),
+            );
     }
 }
 
@@ -18,14 +45,61 @@ fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2d);
 }
 
+// Zooms the camera so the board fills the window left of the side panel, and moves it so the
+// board sits in the middle of that space. World coordinates never change, which keeps
+// `get_board_square` right at any window size.
+fn fit_camera_system(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut camera_query: Query<(&mut Projection, &mut Transform), With<Camera2d>>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+    let panel_width = side_panel_width(window.width());
+    let area = Vec2::new(window.width() - panel_width, window.height());
+    if area.min_element() <= 0.0 {
+        return;
+    }
+    let scale = VIEW_SIZE / area.min_element();
+
+    for (mut projection, mut transform) in camera_query.iter_mut() {
+        if let Projection::Orthographic(ortho) = &mut *projection
+            && ortho.scale != scale
+        {
+            ortho.scale = scale;
+        }
+        let x = panel_width / 2.0 * scale;
+        if transform.translation.x != x {
+            transform.translation.x = x;
+        }
+    }
+}
+
+// The two square colors, shared by all squares of that color so a theme can repaint them at once.
+#[derive(Resource)]
+pub struct SquareMaterials {
+    pub light: Handle<ColorMaterial>,
+    pub dark: Handle<ColorMaterial>,
+}
+
 fn spawn_board(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    orientation: Res<BoardOrientation>,
+    theme: Res<Theme>,
+    asset_server: Res<AssetServer>,
 ) {
     // Material (Square Paint)
-    let white = materials.add(Color::srgb(0.9, 0.9, 0.8));
-    let black = materials.add(Color::srgb(0.4, 0.4, 0.4));
+    let white = materials.add(theme.light_square);
+    let black = materials.add(theme.dark_square);
+    commands.insert_resource(SquareMaterials {
+        light: white.clone(),
+        dark: black.clone(),
+    });
+    if let Some(path) = &theme.board_texture {
+        commands.spawn(board_texture(path, &asset_server));
+    }
 
     let mut board = Vec::new();
 
@@ -46,80 +120,331 @@ fn spawn_board(
         commands.spawn((
             Mesh2d(square),
             MeshMaterial2d(color),
-            Transform::from_translation(get_world_position(y, x, 0.0)),
+            Transform::from_translation(get_world_position(y, x, 0.0, &orientation)),
             Square {
                 x: y as u8,
                 y: x as u8,
             },
+            DespawnOnExit(InGame),
         ));
     }
 }
 
-fn spawn_pieces(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // initial setup -> (PieceType, Location)
-    let white_last_rank = [
-        (PieceKind::Rook, 0, 0),
-        (PieceKind::Knight, 1, 0),
-        (PieceKind::Bishop, 2, 0),
-        (PieceKind::Queen, 3, 0),
-        (PieceKind::King, 4, 0),
-        (PieceKind::Bishop, 5, 0),
-        (PieceKind::Knight, 6, 0),
-        (PieceKind::Rook, 7, 0),
-    ];
-
-    let spawn_piece =
-        |commands: &mut Commands, kind: PieceKind, color: PieceColor, x: u8, y: u8| {
-            let color_str = match color {
-                PieceColor::White => "white",
-                PieceColor::Black => "black",
-            };
-            let kind_str = match kind {
-                PieceKind::Pawn => "pawn",
-                PieceKind::Rook => "rook",
-                PieceKind::Knight => "knight",
-                PieceKind::Bishop => "bishop",
-                PieceKind::Queen => "queen",
-                PieceKind::King => "king",
-            };
+// An image covering the whole board, above the squares and below the coordinates.
+pub fn board_texture(path: &str, asset_server: &AssetServer) -> impl Bundle {
+    (
+        Sprite {
+            image: asset_server.load(path.to_string()),
+            custom_size: Some(Vec2::splat(BOARD_SIZE)),
+            ..default()
+        },
+        Transform::from_xyz(0.0, 0.0, 0.1),
+        BoardTexture,
+        DespawnOnExit(InGame),
+    )
+}
 
-            let path = format!("pieces/{color_str}-{kind_str}.png");
-
-            commands.spawn((
-                Sprite {
-                    image: asset_server.load(path),
-                    ..Default::default()
-                },
-                Transform {
-                    translation: get_world_position(x as usize, y as usize, 1.0),
-                    scale: Vec3::splat(0.8),
-                    ..Default::default()
-                },
-                Piece { kind, color, has_moved: false, start_pos: (x, y) },
-                Square {
-                    x: x as u8,
-                    y: y as u8,
-                },
-            ));
-        };
+// The sprite of a piece standing on the given square.
+pub fn piece_sprite(
+    color: PieceColor,
+    kind: PieceKind,
+    square: (u8, u8),
+    asset_server: &AssetServer,
+    orientation: &BoardOrientation,
+    theme: &Theme,
+) -> (Sprite, Transform) {
+    (
+        Sprite {
+            image: asset_server.load(piece_image_path(theme, color, kind)),
+            ..Default::default()
+        },
+        Transform {
+            translation: get_world_position(square.0 as usize, square.1 as usize, 1.0, orientation),
+            scale: Vec3::splat(PIECE_SCALE),
+            ..Default::default()
+        },
+    )
+}
 
-    for (kind, x, y) in white_last_rank {
-        spawn_piece(&mut commands, kind, PieceColor::White, x, y);
-        spawn_piece(&mut commands, kind, PieceColor::Black, x, 7); // Mirror for black
-    }
+// A piece taking part in the game.
+pub fn piece_bundle(
+    piece: Piece,
+    square: Square,
+    asset_server: &AssetServer,
+    orientation: &BoardOrientation,
+    theme: &Theme,
+) -> impl Bundle {
+    (
+        piece_sprite(
+            piece.color,
+            piece.kind,
+            (square.x, square.y),
+            asset_server,
+            orientation,
+            theme,
+        ),
+        piece,
+        square,
+    )
+}
 
-    // Spawn Pawns
-    for i in 0..8 {
-        spawn_piece(&mut commands, PieceKind::Pawn, PieceColor::White, i, 1);
-        spawn_piece(&mut commands, PieceKind::Pawn, PieceColor::Black, i, 6);
-    }
+pub fn piece_image_path(theme: &Theme, color: PieceColor, kind: PieceKind) -> String {
+    let color_str = match color {
+        PieceColor::White => "white",
+        PieceColor::Black => "black",
+    };
+    let kind_str = match kind {
+        PieceKind::Pawn => "pawn",
+        PieceKind::Rook => "rook",
+        PieceKind::Knight => "knight",
+        PieceKind::Bishop => "bishop",
+        PieceKind::Queen => "queen",
+        PieceKind::King => "king",
+    };
+
+    format!("{}/{color_str}-{kind_str}.png", theme.pieces)
 }
 
 // Helper function to convert Grid Coordinates (0..8) to Pixel Coordinates (-400..400)
-pub fn get_world_position(col: usize, row: usize, z: f32) -> Vec3 {
+pub fn get_world_position(col: usize, row: usize, z: f32, orientation: &BoardOrientation) -> Vec3 {
+    let (col, row) = if orientation.is_flipped() {
+        (7 - col, 7 - row)
+    } else {
+        (col, row)
+    };
+
     Vec3::new(
         col as f32 * TILE_SIZE - OFFSET + TILE_SIZE / 2.0,
         row as f32 * TILE_SIZE - OFFSET + TILE_SIZE / 2.0,
         z,
     )
 }
+
+// The inverse of get_world_position: the square under a point, if it is on the board.
+pub fn get_board_square(world_position: Vec2, orientation: &BoardOrientation) -> Option<(u8, u8)> {
+    let x = ((world_position.x + OFFSET) / TILE_SIZE).floor();
+    let y = ((world_position.y + OFFSET) / TILE_SIZE).floor();
+
+    if !(0.0..8.0).contains(&x) || !(0.0..8.0).contains(&y) {
+        return None;
+    }
+
+    let (x, y) = (x as u8, y as u8);
+    if orientation.is_flipped() {
+        Some((7 - x, 7 - y))
+    } else {
+        Some((x, y))
+    }
+}
+
+fn flip_board_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut orientation: ResMut<BoardOrientation>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyF) {
+        orientation.bottom = match orientation.bottom {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        };
+    }
+}
+
+fn auto_flip_system(
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    mut orientation: ResMut<BoardOrientation>,
+) {
+    // Only right after a move, so flipping by hand still works in between.
+    if !game_state.is_changed() || !orientation.auto_flip {
+        return;
+    }
+    if players.white == PlayerKind::Human && players.black == PlayerKind::Human {
+        orientation.bottom = game_state.turn;
+    }
+}
+
+fn toggle_coordinates_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut display_options: ResMut<DisplayOptions>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyC) {
+        display_options.show_coordinates = !display_options.show_coordinates;
+    }
+}
+
+// File letters go in the corner of the bottom row, rank numbers in the corner of the left column.
+// Both depend on which side is at the bottom, so the labels are respawned whenever it changes.
+fn spawn_coordinates_system(
+    mut commands: Commands,
+    orientation: Res<BoardOrientation>,
+    display_options: Res<DisplayOptions>,
+    theme: Res<Theme>,
+    label_query: Query<Entity, With<CoordinateLabel>>,
+) {
+    let missing = label_query.is_empty() && display_options.show_coordinates;
+    if !orientation.is_changed() && !display_options.is_changed() && !theme.is_changed() && !missing
+    {
+        return;
+    }
+
+    for entity in label_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !display_options.show_coordinates {
+        return;
+    }
+
+    let edge = if orientation.is_flipped() { 7 } else { 0 };
+    let margin = 4.0;
+    let labels = (0..8u8)
+        .map(|file| {
+            let corner = Vec2::new(TILE_SIZE / 2.0 - margin, -TILE_SIZE / 2.0 + margin);
+            (
+                (file, edge),
+                ((b'a' + file) as char).to_string(),
+                corner,
+                Anchor::BOTTOM_RIGHT,
+            )
+        })
+        .chain((0..8u8).map(|rank| {
+            let corner = Vec2::new(-TILE_SIZE / 2.0 + margin, TILE_SIZE / 2.0 - margin);
+            (
+                (edge, rank),
+                (rank + 1).to_string(),
+                corner,
+                Anchor::TOP_LEFT,
+            )
+        }));
+
+    for ((x, y), label, corner, anchor) in labels {
+        // Drawn in the color of the other squares, so they stand out on either.
+        let color = if (x + y) % 2 == 0 {
+            theme.light_square
+        } else {
+            theme.dark_square
+        };
+        let center = get_world_position(x as usize, y as usize, 0.2, &orientation);
+
+        commands.spawn((
+            Text2d::new(label),
+            TextFont {
+                font_size: 18.0,
+                ..default()
+            },
+            TextColor(color),
+            anchor,
+            Transform::from_translation(center + corner.extend(0.0)),
+            CoordinateLabel,
+            DespawnOnExit(InGame),
+        ));
+    }
+}
+
+// Redraws everything that sits on a square after the board was flipped.
+fn orient_board_system(
+    orientation: Res<BoardOrientation>,
+    mut square_query: Query<
+        (&Square, &mut Transform, Option<&mut MoveAnimation>),
+        Without<Dragging>,
+    >,
+) {
+    if !orientation.is_changed() || orientation.is_added() {
+        return;
+    }
+
+    for (square, mut transform, animation) in square_query.iter_mut() {
+        let position = get_world_position(
+            square.x as usize,
+            square.y as usize,
+            transform.translation.z,
+            &orientation,
+        );
+
+        // Flipping mirrors the board around its center.
+        if let Some(mut animation) = animation {
+            animation.from = (-animation.from.truncate()).extend(animation.from.z);
+            animation.to = position.with_z(animation.to.z);
+        }
+        transform.translation = position;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squares_are_found_where_they_are_drawn() {
+        for bottom in [PieceColor::White, PieceColor::Black] {
+            let orientation = BoardOrientation {
+                bottom,
+                auto_flip: false,
+            };
+            for x in 0..8u8 {
+                for y in 0..8u8 {
+                    let center =
+                        get_world_position(x as usize, y as usize, 0.0, &orientation).truncate();
+                    let near_corner = center + Vec2::splat(TILE_SIZE / 2.0 - 1.0);
+                    assert_eq!(get_board_square(center, &orientation), Some((x, y)));
+                    assert_eq!(get_board_square(near_corner, &orientation), Some((x, y)));
+                }
+            }
+            assert_eq!(
+                get_board_square(Vec2::new(-OFFSET - 1.0, 0.0), &orientation),
+                None
+            );
+            assert_eq!(get_board_square(Vec2::new(0.0, OFFSET), &orientation), None);
+        }
+    }
+
+    // Where the label with this text is drawn.
+    fn label_position(app: &mut App, text: &str) -> Vec3 {
+        let mut query = app.world_mut().query::<(&Text2d, &Transform)>();
+        query
+            .iter(app.world())
+            .find(|(label, _)| label.0 == text)
+            .map(|(_, transform)| transform.translation)
+            .expect("the label is drawn")
+    }
+
+    #[test]
+    fn coordinates_follow_the_orientation() {
+        let mut app = App::new();
+        app.init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<DisplayOptions>()
+            .init_resource::<BoardOrientation>()
+            .init_resource::<Theme>()
+            .add_systems(
+                Update,
+                (
+                    toggle_coordinates_system,
+                    spawn_coordinates_system.after(toggle_coordinates_system),
+                ),
+            );
+        app.update();
+        let mut labels = app.world_mut().query::<&CoordinateLabel>();
+        assert_eq!(labels.iter(app.world()).count(), 16);
+        let a = label_position(&mut app, "a");
+        assert!(a.x < 0.0 && a.y < 0.0);
+
+        // With Black at the bottom, the a-file is on the right.
+        app.world_mut().resource_mut::<BoardOrientation>().bottom = PieceColor::Black;
+        app.update();
+        assert_eq!(labels.iter(app.world()).count(), 16);
+        let a = label_position(&mut app, "a");
+        assert!(a.x > 0.0 && a.y < 0.0);
+
+        app.world_mut()
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyC);
+        app.update();
+        assert_eq!(labels.iter(app.world()).count(), 0);
+    }
+
+    #[test]
+    fn black_at_the_bottom_puts_a1_top_right() {
+        let orientation = BoardOrientation::from_args(["--orientation", "black"].map(String::from));
+        let a1 = get_world_position(0, 0, 0.0, &orientation);
+        assert!(a1.x > 0.0 && a1.y > 0.0);
+    }
+}
@@ -0,0 +1,95 @@
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{
+    board::{OFFSET, TILE_SIZE, piece_image_path},
+    chess::{captured_pieces, material_difference},
+    components::{CapturedPieceIcon, MaterialText, PieceColor},
+    resources::{BoardOrientation, MoveHistory},
+    states::InGame,
+    theme::Theme,
+};
+
+const ICON_SIZE: f32 = 32.0;
+// Pieces of the same kind overlap, and there is a little room between kinds.
+const ICON_STEP: f32 = 18.0;
+const GROUP_GAP: f32 = 12.0;
+
+pub struct CapturesPlugin;
+
+impl Plugin for CapturesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_captures_system.run_if(in_state(InGame)));
+    }
+}
+
+// Each side's trophies sit along its own edge of the board: below for the side at the bottom and
+// above for the other. They follow the position on display, so undo and browsing update them too.
+#[allow(clippy::type_complexity)]
+fn update_captures_system(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    orientation: Res<BoardOrientation>,
+    tray_query: Query<Entity, Or<(With<CapturedPieceIcon>, With<MaterialText>)>>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+) {
+    if !history.is_changed() && !orientation.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for entity in tray_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let ply = history.viewing.unwrap_or(history.moves.len());
+    let Some(position) = history.position(ply) else {
+        return;
+    };
+    let difference = material_difference(&position.board);
+
+    for (side, opponent, lead) in [
+        (PieceColor::White, PieceColor::Black, difference),
+        (PieceColor::Black, PieceColor::White, -difference),
+    ] {
+        let y = if side == orientation.bottom {
+            -OFFSET - TILE_SIZE / 4.0
+        } else {
+            OFFSET + TILE_SIZE / 4.0
+        };
+        let mut x = -OFFSET + ICON_SIZE / 2.0;
+        let mut previous = None;
+
+        let positions = (0..=ply).filter_map(|ply| history.position(ply));
+        for kind in captured_pieces(positions, opponent) {
+            if previous.is_some_and(|previous| previous != kind) {
+                x += GROUP_GAP;
+            }
+            commands.spawn((
+                Sprite {
+                    image: asset_server.load(piece_image_path(&theme, opponent, kind)),
+                    custom_size: Some(Vec2::splat(ICON_SIZE)),
+                    ..default()
+                },
+                Transform::from_xyz(x, y, 0.0),
+                CapturedPieceIcon,
+                DespawnOnExit(InGame),
+            ));
+            x += ICON_STEP;
+            previous = Some(kind);
+        }
+
+        if lead > 0 {
+            commands.spawn((
+                Text2d::new(format!("+{lead}")),
+                TextFont {
+                    font_size: 22.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                Anchor::CENTER_LEFT,
+                Transform::from_xyz(x + ICON_SIZE / 2.0, y, 0.0),
+                MaterialText,
+                DespawnOnExit(InGame),
+            ));
+        }
+    }
+}
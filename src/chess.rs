@@ -3,9 +3,17 @@
 use crate::components::{Piece, PieceColor, PieceKind, Square};
 
 type Board<'a> = &'a [(Piece, Square)];
+// (start, end, promotion)
+pub type Move = ((u8, u8), (u8, u8), Option<PieceKind>);
 
-pub fn is_legal_move(piece: &Piece, start: (u8, u8), end: (u8, u8), board: Board) -> bool {
-    if !is_geometrically_valid_move(piece, start, end, board) {
+pub fn is_legal_move(
+    piece: &Piece,
+    start: (u8, u8),
+    end: (u8, u8),
+    board: Board,
+    en_passant: Option<(u8, u8)>,
+) -> bool {
+    if !is_geometrically_valid_move(piece, start, end, board, en_passant) {
         return false;
     }
 
@@ -13,7 +21,9 @@ pub fn is_legal_move(piece: &Piece, start: (u8, u8), end: (u8, u8), board: Board
 
     let mut temp_board = board.to_vec();
     // Remove only the captured piece (if any).
-    temp_board.retain(|(_, square)| square.x != end.0 || square.y != end.1);
+    let captured_square =
+        en_passant_capture_square(piece, start, end, board, en_passant).unwrap_or(end);
+    temp_board.retain(|(_, square)| square.x != captured_square.0 || square.y != captured_square.1);
     // Move the capturing (or moving) piece to the final location (end).
     if let Some((_, square)) = temp_board
         .iter_mut()
@@ -38,12 +48,17 @@ pub fn is_legal_move(piece: &Piece, start: (u8, u8), end: (u8, u8), board: Board
     !is_king_in_check(king_position, piece.color, &temp_board)
 }
 
-pub fn get_legal_moves(piece: &Piece, start: (u8, u8), board: Board) -> Vec<(u8, u8)> {
+pub fn get_legal_moves(
+    piece: &Piece,
+    start: (u8, u8),
+    board: Board,
+    en_passant: Option<(u8, u8)>,
+) -> Vec<(u8, u8)> {
     let mut legal_moves = Vec::new();
 
     for row in 0..8 {
         for col in 0..8 {
-            if is_legal_move(piece, start, (row, col), board) {
+            if is_legal_move(piece, start, (row, col), board, en_passant) {
                 legal_moves.push((row, col));
             }
         }
@@ -52,11 +67,42 @@ pub fn get_legal_moves(piece: &Piece, start: (u8, u8), board: Board) -> Vec<(u8,
     legal_moves
 }
 
+/// Returns the square of the pawn captured en passant if this move is an en passant capture.
+pub fn en_passant_capture_square(
+    piece: &Piece,
+    start: (u8, u8),
+    end: (u8, u8),
+    board: Board,
+    en_passant: Option<(u8, u8)>,
+) -> Option<(u8, u8)> {
+    if piece.kind != PieceKind::Pawn || en_passant != Some(end) || start.0 == end.0 {
+        return None;
+    }
+    // The target square of an en passant capture is always empty.
+    if board
+        .iter()
+        .any(|(_, square)| square.x == end.0 && square.y == end.1)
+    {
+        return None;
+    }
+    Some((end.0, start.1))
+}
+
+/// Returns true if a pawn moving to this square has reached the last rank.
+pub fn is_promotion(piece: &Piece, end: (u8, u8)) -> bool {
+    piece.kind == PieceKind::Pawn
+        && match piece.color {
+            PieceColor::White => end.1 == 7,
+            PieceColor::Black => end.1 == 0,
+        }
+}
+
 fn is_geometrically_valid_move(
     piece: &Piece,
     start: (u8, u8),
     end: (u8, u8),
     board: Board,
+    en_passant: Option<(u8, u8)>,
 ) -> bool {
     if start == end {
         return false;
@@ -78,7 +124,7 @@ fn is_geometrically_valid_move(
     let abs_dy = dy.abs();
 
     match piece.kind {
-        PieceKind::Pawn => is_valid_pawn_move(piece.color, start, end, board),
+        PieceKind::Pawn => is_valid_pawn_move(piece.color, start, end, board, en_passant),
         PieceKind::Rook => (dx == 0 || dy == 0) && is_path_clear(start, end, board),
         PieceKind::Knight => (abs_dx == 1 && abs_dy == 2) || (abs_dx == 2 && abs_dy == 1),
         PieceKind::Bishop => (abs_dx == abs_dy) && is_path_clear(start, end, board),
@@ -97,6 +143,36 @@ fn is_geometrically_valid_move(
     }
 }
 
+/// Returns true if the piece moves like this on an empty board, or could capture this way. Premoves
+/// are checked against this, as the position will have changed by the time they are played.
+pub fn is_possible_premove(piece: &Piece, start: (u8, u8), end: (u8, u8)) -> bool {
+    if start == end {
+        return false;
+    }
+
+    let dx = (end.0 as i8) - (start.0 as i8);
+    let dy = (end.1 as i8) - (start.1 as i8);
+    let (abs_dx, abs_dy) = (dx.abs(), dy.abs());
+
+    match piece.kind {
+        PieceKind::Pawn => {
+            let (direction, start_rank) = match piece.color {
+                PieceColor::White => (1, 1),
+                PieceColor::Black => (-1, 6),
+            };
+            (dy == direction && abs_dx <= 1)
+                || (dx == 0 && dy == 2 * direction && start.1 == start_rank)
+        }
+        PieceKind::Rook => dx == 0 || dy == 0,
+        PieceKind::Knight => (abs_dx == 1 && abs_dy == 2) || (abs_dx == 2 && abs_dy == 1),
+        PieceKind::Bishop => abs_dx == abs_dy,
+        PieceKind::Queen => dx == 0 || dy == 0 || abs_dx == abs_dy,
+        PieceKind::King => {
+            (abs_dx <= 1 && abs_dy <= 1) || (!piece.has_moved && abs_dx == 2 && dy == 0)
+        }
+    }
+}
+
 /// Returns true if the path between the start and end squares does not contain any other piece.
 fn is_path_clear(start: (u8, u8), end: (u8, u8), board: Board) -> bool {
     let distance_x = (end.0 as i8) - (start.0 as i8);
@@ -133,7 +209,13 @@ fn is_path_clear(start: (u8, u8), end: (u8, u8), board: Board) -> bool {
     true
 }
 
-fn is_valid_pawn_move(color: PieceColor, start: (u8, u8), end: (u8, u8), board: Board) -> bool {
+fn is_valid_pawn_move(
+    color: PieceColor,
+    start: (u8, u8),
+    end: (u8, u8),
+    board: Board,
+    en_passant: Option<(u8, u8)>,
+) -> bool {
     let dx = (end.0 as i8) - (start.0 as i8);
     let dy = (end.1 as i8) - (start.1 as i8);
 
@@ -165,8 +247,9 @@ fn is_valid_pawn_move(color: PieceColor, start: (u8, u8), end: (u8, u8), board:
 
     // Diagonal Capture: dy == direction limits the capture to only happen diagonally forwards wrt the piece color.
     // The check for the enemy piece is already handled in input_system (if there is same color piece diagonal to a pawn, that piece will get selected instead of being captured)
+    // En passant: the target square is empty, but it is the square the enemy pawn just skipped.
     if dx.abs() == 1 && dy == direction {
-        return target_square_has_piece;
+        return target_square_has_piece || en_passant == Some(end);
     }
 
     false
@@ -177,7 +260,13 @@ pub fn is_king_in_check(king_position: (u8, u8), color: PieceColor, board: Board
         .iter()
         .filter(|(enemy_piece, _)| enemy_piece.color != color)
         .any(|(enemy_piece, square)| {
-            is_geometrically_valid_move(enemy_piece, (square.x, square.y), king_position, board)
+            is_geometrically_valid_move(
+                enemy_piece,
+                (square.x, square.y),
+                king_position,
+                board,
+                None,
+            )
         })
 }
 
@@ -262,3 +351,743 @@ fn is_castling_possible(king: &Piece, side: CastleSide, board: Board) -> bool {
 
     true
 }
+
+/// Returns the algebraic name of a square, e.g. (4, 3) => "e4".
+pub fn square_name(square: (u8, u8)) -> String {
+    format!("{}{}", (b'a' + square.0) as char, square.1 + 1)
+}
+
+/// Parses an algebraic square name, e.g. "e4" => (4, 3).
+pub fn parse_square(name: &str) -> Option<(u8, u8)> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1])
+    {
+        return None;
+    }
+    Some((bytes[0] - b'a', bytes[1] - b'1'))
+}
+
+/// The uppercase letter used for a piece kind in FEN and SAN (pawns are 'P').
+pub fn piece_letter(kind: PieceKind) -> char {
+    match kind {
+        PieceKind::Pawn => 'P',
+        PieceKind::Rook => 'R',
+        PieceKind::Knight => 'N',
+        PieceKind::Bishop => 'B',
+        PieceKind::Queen => 'Q',
+        PieceKind::King => 'K',
+    }
+}
+
+fn piece_kind_from_letter(letter: char) -> Option<PieceKind> {
+    match letter.to_ascii_uppercase() {
+        'P' => Some(PieceKind::Pawn),
+        'R' => Some(PieceKind::Rook),
+        'N' => Some(PieceKind::Knight),
+        'B' => Some(PieceKind::Bishop),
+        'Q' => Some(PieceKind::Queen),
+        'K' => Some(PieceKind::King),
+        _ => None,
+    }
+}
+
+/// Formats a move in UCI long algebraic notation, e.g. "e2e4" or "e7e8q".
+pub fn to_uci_move(start: (u8, u8), end: (u8, u8), promotion: Option<PieceKind>) -> String {
+    let mut uci = format!("{}{}", square_name(start), square_name(end));
+    if let Some(kind) = promotion {
+        uci.push(piece_letter(kind).to_ascii_lowercase());
+    }
+    uci
+}
+
+/// Parses a move in UCI long algebraic notation into (start, end, promotion).
+pub fn parse_uci_move(uci: &str) -> Option<Move> {
+    if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+        return None;
+    }
+    let start = parse_square(&uci[0..2])?;
+    let end = parse_square(&uci[2..4])?;
+    let promotion = match uci[4..].chars().next() {
+        Some(letter) => match piece_kind_from_letter(letter)? {
+            PieceKind::Pawn | PieceKind::King => return None,
+            kind => Some(kind),
+        },
+        None => None,
+    };
+    Some((start, end, promotion))
+}
+
+fn castling_rights(board: Board) -> String {
+    let mut rights = String::new();
+    for (color, rank) in [(PieceColor::White, 0), (PieceColor::Black, 7)] {
+        let king_at_home = board.iter().any(|(p, s)| {
+            p.kind == PieceKind::King && p.color == color && !p.has_moved && (s.x, s.y) == (4, rank)
+        });
+        if !king_at_home {
+            continue;
+        }
+        for (rook_file, letter) in [(7, 'K'), (0, 'Q')] {
+            let rook_at_home = board.iter().any(|(p, s)| {
+                p.kind == PieceKind::Rook
+                    && p.color == color
+                    && !p.has_moved
+                    && p.start_pos == (rook_file, rank)
+                    && (s.x, s.y) == (rook_file, rank)
+            });
+            if rook_at_home {
+                rights.push(match color {
+                    PieceColor::White => letter,
+                    PieceColor::Black => letter.to_ascii_lowercase(),
+                });
+            }
+        }
+    }
+    if rights.is_empty() {
+        rights.push('-');
+    }
+    rights
+}
+
+/// Describes the position in Forsyth-Edwards Notation.
+pub fn to_fen(
+    board: Board,
+    turn: PieceColor,
+    en_passant: Option<(u8, u8)>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+) -> String {
+    let mut placement = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            match board.iter().find(|(_, s)| s.x == file && s.y == rank) {
+                Some((piece, _)) => {
+                    if empty > 0 {
+                        placement.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    let letter = piece_letter(piece.kind);
+                    placement.push(match piece.color {
+                        PieceColor::White => letter,
+                        PieceColor::Black => letter.to_ascii_lowercase(),
+                    });
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            placement.push_str(&empty.to_string());
+        }
+        if rank > 0 {
+            placement.push('/');
+        }
+    }
+
+    let side = match turn {
+        PieceColor::White => "w",
+        PieceColor::Black => "b",
+    };
+    let en_passant = en_passant
+        .map(square_name)
+        .unwrap_or_else(|| "-".to_string());
+
+    format!(
+        "{placement} {side} {} {en_passant} {halfmove_clock} {fullmove_number}",
+        castling_rights(board)
+    )
+}
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Reads a position in Forsyth-Edwards Notation. Castling rights become the `has_moved` flags of
+/// the king and rooks, the way the board keeps track of them during a game.
+pub fn parse_fen(fen: &str) -> Option<Position> {
+    let mut fields = fen.split_whitespace();
+    let placement = fields.next()?;
+    let turn = match fields.next().unwrap_or("w") {
+        "w" => PieceColor::White,
+        "b" => PieceColor::Black,
+        _ => return None,
+    };
+    let castling = fields.next().unwrap_or("-");
+    let en_passant = match fields.next().unwrap_or("-") {
+        "-" => None,
+        square => Some(parse_square(square)?),
+    };
+    let halfmove_clock = fields.next().map_or(Some(0), |n| n.parse().ok())?;
+    let fullmove_number = fields.next().map_or(Some(1), |n| n.parse().ok())?;
+
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return None;
+    }
+    let mut board = Vec::new();
+    for (i, pieces) in ranks.iter().enumerate() {
+        let rank = 7 - i as u8;
+        let mut file = 0u8;
+        for letter in pieces.chars() {
+            if let Some(empty) = letter.to_digit(10) {
+                // A run of empty squares is 1 to 8 long and can't run past the h-file.
+                file = file
+                    .checked_add(empty as u8)
+                    .filter(|&file| (1..=8).contains(&empty) && file <= 8)?;
+                continue;
+            }
+            if file > 7 {
+                return None;
+            }
+
+            let kind = piece_kind_from_letter(letter)?;
+            let (color, home) = if letter.is_ascii_uppercase() {
+                (PieceColor::White, 0)
+            } else {
+                (PieceColor::Black, 7)
+            };
+            let right = |side: char| match color {
+                PieceColor::White => castling.contains(side),
+                PieceColor::Black => castling.contains(side.to_ascii_lowercase()),
+            };
+            let has_moved = match (kind, file) {
+                (PieceKind::King, 4) if rank == home => !right('K') && !right('Q'),
+                (PieceKind::King, _) => true,
+                (PieceKind::Rook, 7) if rank == home => !right('K'),
+                (PieceKind::Rook, 0) if rank == home => !right('Q'),
+                _ => false,
+            };
+
+            board.push((
+                Piece {
+                    kind,
+                    color,
+                    has_moved,
+                    start_pos: (file, rank),
+                },
+                Square { x: file, y: rank },
+            ));
+            file += 1;
+        }
+        if file != 8 {
+            return None;
+        }
+    }
+
+    // Anything else would break the move rules.
+    for color in [PieceColor::White, PieceColor::Black] {
+        let kings = board
+            .iter()
+            .filter(|(p, _)| p.kind == PieceKind::King && p.color == color)
+            .count();
+        if kings != 1 {
+            return None;
+        }
+    }
+
+    Some(Position {
+        board,
+        turn,
+        en_passant,
+        halfmove_clock,
+        fullmove_number,
+    })
+}
+
+// A complete snapshot of a position, e.g. one from the move history.
+#[derive(Clone, Debug)]
+pub struct Position {
+    pub board: Vec<(Piece, Square)>,
+    pub turn: PieceColor,
+    pub en_passant: Option<(u8, u8)>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Position {
+    pub fn fen(&self) -> String {
+        to_fen(
+            &self.board,
+            self.turn,
+            self.en_passant,
+            self.halfmove_clock,
+            self.fullmove_number,
+        )
+    }
+
+    /// The part of the FEN that decides whether two positions are the same for repetitions,
+    /// leaving out the move counters.
+    pub fn repetition_key(&self) -> String {
+        let fen = self.fen();
+        fen.split(' ').take(4).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Returns true if the side to move has no legal move.
+    pub fn is_stuck(&self) -> bool {
+        !has_legal_moves(self.turn, &self.board, self.en_passant)
+    }
+
+    /// Returns the square of the side to move's king if it is in check.
+    pub fn checked_king(&self) -> Option<(u8, u8)> {
+        self.board
+            .iter()
+            .find(|(p, _)| p.kind == PieceKind::King && p.color == self.turn)
+            .map(|(_, s)| (s.x, s.y))
+            .filter(|&king| is_king_in_check(king, self.turn, &self.board))
+    }
+
+    /// Whether the side to move may play this move: it is legal, with a promotion exactly when a
+    /// pawn reaches the last rank.
+    pub fn is_legal(&self, (start, end, promotion): Move) -> bool {
+        self.board.iter().any(|(piece, square)| {
+            (square.x, square.y) == start
+                && piece.color == self.turn
+                && is_legal_move(piece, start, end, &self.board, self.en_passant)
+                && is_promotion(piece, end) == promotion.is_some()
+        })
+    }
+
+    /// The position after a move, which is taken to be legal.
+    pub fn play(&self, start: (u8, u8), end: (u8, u8), promotion: Option<PieceKind>) -> Position {
+        let mut board = self.board.clone();
+        let pawn_move = board
+            .iter()
+            .any(|(p, s)| (s.x, s.y) == start && p.kind == PieceKind::Pawn);
+        let en_passant = make_move(&mut board, start, end, promotion, self.en_passant);
+        let capture = board.len() < self.board.len();
+
+        Position {
+            board,
+            turn: match self.turn {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White,
+            },
+            en_passant,
+            halfmove_clock: if pawn_move || capture {
+                0
+            } else {
+                self.halfmove_clock + 1
+            },
+            fullmove_number: self.fullmove_number + u32::from(self.turn == PieceColor::Black),
+        }
+    }
+
+    /// Finds the legal move written in SAN. Check marks, annotation glyphs like `!?` and castling
+    /// written with zeros are accepted.
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']).replace('0', "O");
+
+        for (piece, square) in self.board.iter().filter(|(p, _)| p.color == self.turn) {
+            let start = (square.x, square.y);
+            for end in get_legal_moves(piece, start, &self.board, self.en_passant) {
+                let promotions: &[Option<PieceKind>] = if is_promotion(piece, end) {
+                    &[
+                        Some(PieceKind::Queen),
+                        Some(PieceKind::Rook),
+                        Some(PieceKind::Bishop),
+                        Some(PieceKind::Knight),
+                    ]
+                } else {
+                    &[None]
+                };
+                for &promotion in promotions {
+                    let candidate = to_san(&self.board, start, end, promotion, self.en_passant);
+                    if candidate.trim_end_matches(['+', '#']) == san {
+                        return Some((start, end, promotion));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Plays a move on a board without any checks and returns the new en passant target.
+pub fn make_move(
+    board: &mut Vec<(Piece, Square)>,
+    start: (u8, u8),
+    end: (u8, u8),
+    promotion: Option<PieceKind>,
+    en_passant: Option<(u8, u8)>,
+) -> Option<(u8, u8)> {
+    let piece = board
+        .iter()
+        .find(|(_, s)| s.x == start.0 && s.y == start.1)
+        .map(|(p, _)| *p)?;
+
+    let captured_square =
+        en_passant_capture_square(&piece, start, end, board, en_passant).unwrap_or(end);
+    board.retain(|(_, s)| s.x != captured_square.0 || s.y != captured_square.1);
+
+    let dx = end.0 as i8 - start.0 as i8;
+    let dy = end.1 as i8 - start.1 as i8;
+    if piece.kind == PieceKind::King && dx.abs() == 2 {
+        let (rook_start, rook_dest) = if dx > 0 { (7, 5) } else { (0, 3) };
+        if let Some((rook, square)) = board
+            .iter_mut()
+            .find(|(_, s)| s.x == rook_start && s.y == start.1)
+        {
+            square.x = rook_dest;
+            rook.has_moved = true;
+        }
+    }
+
+    if let Some((moved, square)) = board
+        .iter_mut()
+        .find(|(_, s)| s.x == start.0 && s.y == start.1)
+    {
+        square.x = end.0;
+        square.y = end.1;
+        moved.has_moved = true;
+        if is_promotion(&piece, end) {
+            moved.kind = promotion.unwrap_or(PieceKind::Queen);
+        }
+    }
+
+    if piece.kind == PieceKind::Pawn && dy.abs() == 2 {
+        Some((start.0, (start.1 + end.1) / 2))
+    } else {
+        None
+    }
+}
+
+/// Returns true if the given side has at least one legal move.
+pub fn has_legal_moves(color: PieceColor, board: Board, en_passant: Option<(u8, u8)>) -> bool {
+    board
+        .iter()
+        .filter(|(piece, _)| piece.color == color)
+        .any(|(piece, square)| !get_legal_moves(piece, (square.x, square.y), board, en_passant).is_empty())
+}
+
+fn is_in_check(color: PieceColor, board: Board) -> bool {
+    board
+        .iter()
+        .find(|(p, _)| p.kind == PieceKind::King && p.color == color)
+        .is_some_and(|(_, s)| is_king_in_check((s.x, s.y), color, board))
+}
+
+/// Describes a legal move in Standard Algebraic Notation, e.g. "Nbd7", "exd6", "e8=Q+" or "O-O#".
+pub fn to_san(
+    board: Board,
+    start: (u8, u8),
+    end: (u8, u8),
+    promotion: Option<PieceKind>,
+    en_passant: Option<(u8, u8)>,
+) -> String {
+    let Some(piece) = board
+        .iter()
+        .find(|(_, s)| s.x == start.0 && s.y == start.1)
+        .map(|(p, _)| *p)
+    else {
+        return to_uci_move(start, end, promotion);
+    };
+
+    let is_capture = board.iter().any(|(_, s)| s.x == end.0 && s.y == end.1)
+        || en_passant_capture_square(&piece, start, end, board, en_passant).is_some();
+    let dx = end.0 as i8 - start.0 as i8;
+
+    let mut san = String::new();
+    if piece.kind == PieceKind::King && dx.abs() == 2 {
+        san.push_str(if dx > 0 { "O-O" } else { "O-O-O" });
+    } else if piece.kind == PieceKind::Pawn {
+        if is_capture {
+            san.push((b'a' + start.0) as char);
+            san.push('x');
+        }
+        san.push_str(&square_name(end));
+        if is_promotion(&piece, end) {
+            san.push('=');
+            san.push(piece_letter(promotion.unwrap_or(PieceKind::Queen)));
+        }
+    } else {
+        san.push(piece_letter(piece.kind));
+
+        // Other pieces of the same kind that could also reach the target square.
+        let rivals: Vec<(u8, u8)> = board
+            .iter()
+            .filter(|(p, s)| {
+                p.kind == piece.kind && p.color == piece.color && (s.x, s.y) != start
+            })
+            .filter(|(p, s)| is_legal_move(p, (s.x, s.y), end, board, en_passant))
+            .map(|(_, s)| (s.x, s.y))
+            .collect();
+        if !rivals.is_empty() {
+            let file = (b'a' + start.0) as char;
+            let rank = (b'1' + start.1) as char;
+            if rivals.iter().all(|r| r.0 != start.0) {
+                san.push(file);
+            } else if rivals.iter().all(|r| r.1 != start.1) {
+                san.push(rank);
+            } else {
+                san.push(file);
+                san.push(rank);
+            }
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(end));
+    }
+
+    let mut after = board.to_vec();
+    let en_passant = make_move(&mut after, start, end, promotion, en_passant);
+    let opponent = match piece.color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    if is_in_check(opponent, &after) {
+        san.push(if has_legal_moves(opponent, &after, en_passant) {
+            '+'
+        } else {
+            '#'
+        });
+    }
+
+    san
+}
+
+/// Converts a line of UCI moves into SAN, stopping at the first move that is not legal.
+pub fn uci_line_to_san(
+    board: Board,
+    turn: PieceColor,
+    en_passant: Option<(u8, u8)>,
+    line: &[String],
+) -> Vec<String> {
+    let mut board = board.to_vec();
+    let mut turn = turn;
+    let mut en_passant = en_passant;
+    let mut sans = Vec::new();
+
+    for uci in line {
+        let Some((start, end, promotion)) = parse_uci_move(uci) else {
+            break;
+        };
+        let Some((piece, _)) = board
+            .iter()
+            .find(|(p, s)| s.x == start.0 && s.y == start.1 && p.color == turn)
+        else {
+            break;
+        };
+        if !is_legal_move(piece, start, end, &board, en_passant) {
+            break;
+        }
+
+        sans.push(to_san(&board, start, end, promotion, en_passant));
+        en_passant = make_move(&mut board, start, end, promotion, en_passant);
+        turn = match turn {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        };
+    }
+
+    sans
+}
+
+/// The usual material value of a piece in pawns. The king is never traded, so it counts as nothing.
+pub fn piece_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 1,
+        PieceKind::Knight | PieceKind::Bishop => 3,
+        PieceKind::Rook => 5,
+        PieceKind::Queen => 9,
+        PieceKind::King => 0,
+    }
+}
+
+/// White's material minus Black's, in pawns.
+pub fn material_difference(board: Board) -> i32 {
+    board
+        .iter()
+        .map(|(piece, _)| match piece.color {
+            PieceColor::White => piece_value(piece.kind),
+            PieceColor::Black => -piece_value(piece.kind),
+        })
+        .sum()
+}
+
+/// The pieces of the given color captured over the positions of a game, from its start, grouped
+/// by kind from pawns to queens. Pieces missing from a set-up start position were never captured.
+pub fn captured_pieces<'a>(
+    positions: impl IntoIterator<Item = &'a Position>,
+    color: PieceColor,
+) -> Vec<PieceKind> {
+    const KINDS: [PieceKind; 5] = [
+        PieceKind::Pawn,
+        PieceKind::Knight,
+        PieceKind::Bishop,
+        PieceKind::Rook,
+        PieceKind::Queen,
+    ];
+    let count = |board: Board, kind: PieceKind| {
+        board
+            .iter()
+            .filter(|(p, _)| p.color == color && p.kind == kind)
+            .count()
+    };
+
+    let mut captured = [0; KINDS.len()];
+    let mut positions = positions.into_iter();
+    let Some(mut before) = positions.next() else {
+        return Vec::new();
+    };
+    for after in positions {
+        // Only the opponent's moves take pieces; on our own move a pawn may just have promoted.
+        if before.turn != color {
+            for (captured, &kind) in captured.iter_mut().zip(&KINDS) {
+                *captured += count(&before.board, kind).saturating_sub(count(&after.board, kind));
+            }
+        }
+        before = after;
+    }
+
+    KINDS
+        .iter()
+        .zip(captured)
+        .flat_map(|(&kind, n)| std::iter::repeat_n(kind, n))
+        .collect()
+}
+
+/// Returns false if the side has only its king, or its king and a single knight or bishop, which
+/// is not enough to mate.
+pub fn has_mating_material(board: Board, color: PieceColor) -> bool {
+    let mut pieces = board
+        .iter()
+        .filter(|(p, _)| p.color == color && p.kind != PieceKind::King);
+    match (pieces.next(), pieces.next()) {
+        (None, _) => false,
+        (Some((p, _)), None) => !matches!(p.kind, PieceKind::Knight | PieceKind::Bishop),
+        _ => true,
+    }
+}
+
+/// Returns true if neither side can ever mate: kings alone, a king and a single knight or bishop
+/// against a bare king, or only bishops left and all of them on squares of one color.
+pub fn is_dead_position(board: Board) -> bool {
+    let pieces: Vec<_> = board
+        .iter()
+        .filter(|(p, _)| p.kind != PieceKind::King)
+        .collect();
+    match pieces.as_slice() {
+        [] => true,
+        [(piece, _)] => matches!(piece.kind, PieceKind::Knight | PieceKind::Bishop),
+        [(_, first), ..] => pieces.iter().all(|(piece, square)| {
+            piece.kind == PieceKind::Bishop && (square.x + square.y) % 2 == (first.x + first.y) % 2
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(turn: PieceColor, pieces: &[(PieceColor, PieceKind, (u8, u8))]) -> Position {
+        let board = pieces
+            .iter()
+            .map(|&(color, kind, (x, y))| {
+                let piece = Piece {
+                    color,
+                    kind,
+                    has_moved: false,
+                    start_pos: (x, y),
+                };
+                (piece, Square { x, y })
+            })
+            .collect();
+        Position {
+            board,
+            turn,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    #[test]
+    fn captures_count_from_the_start_position() {
+        use PieceColor::{Black, White};
+        // Neither side starts with a full set, and Black promotes by taking the rook.
+        let start = position(
+            Black,
+            &[
+                (White, PieceKind::King, (4, 0)),
+                (White, PieceKind::Rook, (0, 0)),
+                (Black, PieceKind::King, (4, 7)),
+                (Black, PieceKind::Pawn, (1, 1)),
+            ],
+        );
+        let after = position(
+            White,
+            &[
+                (White, PieceKind::King, (4, 0)),
+                (Black, PieceKind::King, (4, 7)),
+                (Black, PieceKind::Queen, (0, 0)),
+            ],
+        );
+
+        assert!(captured_pieces([&start], White).is_empty());
+        assert_eq!(captured_pieces([&start, &after], White), [PieceKind::Rook]);
+        assert!(captured_pieces([&start, &after], Black).is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_fen_ranks() {
+        assert!(parse_fen(START_FEN).is_some());
+        for placement in [
+            "4k3/8/8/8/8/8/8/4K39",
+            "4k3/8/8/8/8/8/8/4K0003",
+            "4k3/8/8/8/8/8/8/9999999994K3",
+            "4k3/8/8/8/8/8/8/44K",
+            "4k3/8/8/8/8/8/8/4K2",
+        ] {
+            assert!(parse_fen(placement).is_none(), "{placement}");
+        }
+    }
+
+    #[test]
+    fn fen_survives_a_round_trip() {
+        for fen in [
+            START_FEN,
+            "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 12",
+            "8/8/8/8/8/8/6k1/4K3 b - - 37 80",
+        ] {
+            assert_eq!(parse_fen(fen).unwrap().fen(), fen);
+        }
+    }
+
+    #[test]
+    fn dead_positions() {
+        use PieceColor::{Black, White};
+        let dead = |pieces: &[(PieceColor, PieceKind, (u8, u8))]| {
+            let mut all = vec![
+                (White, PieceKind::King, (4, 0)),
+                (Black, PieceKind::King, (4, 7)),
+            ];
+            all.extend_from_slice(pieces);
+            is_dead_position(&position(White, &all).board)
+        };
+        assert!(dead(&[]));
+        assert!(dead(&[(White, PieceKind::Knight, (5, 0))]));
+        assert!(dead(&[(Black, PieceKind::Bishop, (5, 7))]));
+        // Bishops on squares of the same color.
+        assert!(dead(&[
+            (Black, PieceKind::Bishop, (5, 7)),
+            (White, PieceKind::Bishop, (2, 0)),
+        ]));
+
+        // Mates exist, however unlikely.
+        assert!(!dead(&[
+            (Black, PieceKind::Knight, (5, 7)),
+            (White, PieceKind::Knight, (5, 0)),
+        ]));
+        assert!(!dead(&[
+            (Black, PieceKind::Knight, (5, 7)),
+            (White, PieceKind::Bishop, (5, 0)),
+        ]));
+        assert!(!dead(&[
+            (Black, PieceKind::Bishop, (5, 7)),
+            (White, PieceKind::Bishop, (5, 0)),
+        ]));
+        assert!(!dead(&[
+            (White, PieceKind::Knight, (5, 0)),
+            (White, PieceKind::Knight, (6, 0)),
+        ]));
+    }
+}
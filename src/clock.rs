@@ -0,0 +1,360 @@
+// Chess clocks. A time control is a list of stages, each with its own base time and bonus per move.
+
+use std::time::Duration;
+
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{
+    board::{OFFSET, TILE_SIZE},
+    chess::has_mating_material,
+    components::{ClockText, Piece, PieceColor, Square},
+    events::NewGameEvent,
+    resources::{
+        BoardOrientation, GameEndReason, GameResult, GameSetup, GameState, MoveHistory, PlayerKind,
+        Players,
+    },
+    states::{AppState, InGame},
+};
+
+// Below this, a clock is drawn in red and shows tenths of a second.
+const LOW_TIME: Duration = Duration::from_secs(20);
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(InGame), start_clocks)
+            .add_observer(on_new_game)
+            .add_systems(
+                Update,
+                (
+                    tick_clock_system.run_if(in_state(AppState::Playing)),
+                    update_clock_text_system.after(tick_clock_system),
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeBonus {
+    None,
+    // Fischer: added after every move.
+    Increment(Duration),
+    // Simple (US) delay: the clock only starts running after this much of each move.
+    Delay(Duration),
+    // Bronstein: the time used for a move is given back, up to this much.
+    Bronstein(Duration),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeStage {
+    // Moves to be played in this stage, or None if it lasts for the rest of the game.
+    pub moves: Option<u32>,
+    pub base: Duration,
+    pub bonus: TimeBonus,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimeControl {
+    pub stages: Vec<TimeStage>,
+}
+
+impl TimeControl {
+    /// Parses stages separated by `:`, each `[moves/]minutes` followed by `+seconds` for an
+    /// increment, `dseconds` for a simple delay or `bseconds` for a Bronstein delay. For example
+    /// `5+3`, `90d5` or `40/90+30:30+30`. When every stage has a move count, the last one repeats.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let stages = spec
+            .split(':')
+            .map(|stage| {
+                let (moves, rest) = match stage.split_once('/') {
+                    Some((moves, rest)) => (Some(moves.parse().ok()?), rest),
+                    None => (None, stage),
+                };
+                let split = rest.find(['+', 'd', 'b']).unwrap_or(rest.len());
+                let (minutes, bonus) = rest.split_at(split);
+                // Rejects negative, NaN, infinite and overlong times rather than panicking on them.
+                let duration = |secs: f64| Duration::try_from_secs_f64(secs).ok();
+                let base = duration(minutes.parse::<f64>().ok()? * 60.0)?;
+
+                let seconds = |s: &str| s.parse::<f64>().ok().and_then(duration);
+                let bonus = match bonus.split_at_checked(1) {
+                    None => TimeBonus::None,
+                    Some(("+", s)) => TimeBonus::Increment(seconds(s)?),
+                    Some(("d", s)) => TimeBonus::Delay(seconds(s)?),
+                    Some(("b", s)) => TimeBonus::Bronstein(seconds(s)?),
+                    Some(_) => return None,
+                };
+                Some(TimeStage { moves, base, bonus })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        (!stages.is_empty()).then_some(Self { stages })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Clock {
+    pub remaining: Duration,
+    stage: usize,
+    moves_in_stage: u32,
+    // Time spent on the current move, for the delays.
+    spent: Duration,
+}
+
+impl Clock {
+    pub fn new(control: &TimeControl) -> Self {
+        Self {
+            remaining: control.stages[0].base,
+            stage: 0,
+            moves_in_stage: 0,
+            spent: Duration::ZERO,
+        }
+    }
+
+    pub fn tick(&mut self, control: &TimeControl, delta: Duration) {
+        let delay = match control.stages[self.stage].bonus {
+            TimeBonus::Delay(delay) => delay,
+            _ => Duration::ZERO,
+        };
+        let counted = (self.spent + delta).saturating_sub(delay.max(self.spent));
+        self.spent += delta;
+        self.remaining = self.remaining.saturating_sub(counted);
+    }
+
+    pub fn complete_move(&mut self, control: &TimeControl) {
+        let stage = control.stages[self.stage];
+        match stage.bonus {
+            TimeBonus::Increment(increment) => self.remaining += increment,
+            TimeBonus::Bronstein(delay) => self.remaining += self.spent.min(delay),
+            TimeBonus::None | TimeBonus::Delay(_) => {}
+        }
+        self.spent = Duration::ZERO;
+
+        self.moves_in_stage += 1;
+        if stage.moves == Some(self.moves_in_stage) {
+            self.stage = (self.stage + 1).min(control.stages.len() - 1);
+            self.moves_in_stage = 0;
+            self.remaining += control.stages[self.stage].base;
+        }
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.remaining < LOW_TIME
+    }
+}
+
+#[derive(Resource)]
+pub struct Clocks {
+    pub control: TimeControl,
+    pub white: Clock,
+    pub black: Clock,
+    // Moves seen so far, to notice when a move has been completed.
+    moves: usize,
+    // Times from a game server for after this many moves, set once the move is in.
+    synced: Option<(usize, Duration, Duration)>,
+}
+
+impl Clocks {
+    pub fn get(&self, color: PieceColor) -> &Clock {
+        match color {
+            PieceColor::White => &self.white,
+            PieceColor::Black => &self.black,
+        }
+    }
+
+    fn get_mut(&mut self, color: PieceColor) -> &mut Clock {
+        match color {
+            PieceColor::White => &mut self.white,
+            PieceColor::Black => &mut self.black,
+        }
+    }
+
+    /// Sets both clocks to the times a game server gave for after `ply` moves.
+    pub fn sync(&mut self, ply: usize, white: Duration, black: Duration) {
+        self.synced = Some((ply, white, black));
+    }
+}
+
+/// The result of a game in which `flagged` ran out of time. It is a draw if the opponent could
+/// never mate.
+pub fn timeout_result(flagged: PieceColor, board: &[(Piece, Square)]) -> GameResult {
+    let opponent = match flagged {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    if has_mating_material(board, opponent) {
+        GameResult {
+            winner: Some(opponent),
+            reason: GameEndReason::Timeout,
+        }
+    } else {
+        GameResult {
+            winner: None,
+            reason: GameEndReason::TimeoutVsInsufficientMaterial,
+        }
+    }
+}
+
+// A new game gets fresh clocks with the time control from the setup, if it has one.
+fn start_clocks(mut commands: Commands, setup: Res<GameSetup>) {
+    let Some(control) = setup.time_control.as_deref().and_then(TimeControl::parse) else {
+        commands.remove_resource::<Clocks>();
+        return;
+    };
+
+    commands.insert_resource(Clocks {
+        white: Clock::new(&control),
+        black: Clock::new(&control),
+        control,
+        moves: 0,
+        synced: None,
+    });
+
+    for color in [PieceColor::White, PieceColor::Black] {
+        commands.spawn((
+            Text2d::new(""),
+            TextFont {
+                font_size: 32.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            Anchor::CENTER_RIGHT,
+            Transform::default(),
+            ClockText(color),
+            DespawnOnExit(InGame),
+        ));
+    }
+}
+
+fn on_new_game(_event: On<NewGameEvent>, clocks: Option<ResMut<Clocks>>) {
+    if let Some(mut clocks) = clocks {
+        clocks.white = Clock::new(&clocks.control);
+        clocks.black = Clock::new(&clocks.control);
+        clocks.moves = 0;
+        clocks.synced = None;
+    }
+}
+
+// Runs the clock of the side to move, once the first move has been made. A flag fall ends the game,
+// except that a remote player's flag is left to the other end of the connection.
+pub fn tick_clock_system(
+    time: Res<Time>,
+    clocks: Option<ResMut<Clocks>>,
+    history: Res<MoveHistory>,
+    mut game_state: ResMut<GameState>,
+    players: Res<Players>,
+    piece_query: Query<(&Piece, &Square)>,
+) {
+    let Some(mut clocks) = clocks else {
+        return;
+    };
+
+    if history.moves.len() != clocks.moves {
+        // Taking a move back does not give the time back.
+        if history.moves.len() > clocks.moves {
+            let mover = match game_state.turn {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White,
+            };
+            let control = clocks.control.clone();
+            clocks.get_mut(mover).complete_move(&control);
+        }
+        clocks.moves = history.moves.len();
+    }
+    if let Some((ply, white, black)) = clocks.synced {
+        if ply == history.moves.len() {
+            clocks.white.remaining = white;
+            clocks.black.remaining = black;
+        }
+        if ply <= history.moves.len() {
+            clocks.synced = None;
+        }
+    }
+
+    if history.moves.is_empty() || game_state.result.is_some() {
+        return;
+    }
+
+    let turn = game_state.turn;
+    let control = clocks.control.clone();
+    let clock = clocks.get_mut(turn);
+    clock.tick(&control, time.delta());
+    if !clock.remaining.is_zero() || players.get(turn) == PlayerKind::Remote {
+        return;
+    }
+
+    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+    game_state.result = Some(timeout_result(turn, &board));
+}
+
+fn format_clock(remaining: Duration, low: bool) -> String {
+    let total = remaining.as_secs();
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else if low {
+        format!("{minutes}:{seconds:02}.{}", remaining.subsec_millis() / 100)
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+// Each clock sits at the right end of its side's edge of the board. The running one is bright,
+// and any clock low on time turns red.
+fn update_clock_text_system(
+    clocks: Option<Res<Clocks>>,
+    game_state: Res<GameState>,
+    orientation: Res<BoardOrientation>,
+    mut text_query: Query<(&ClockText, &mut Text2d, &mut TextColor, &mut Transform)>,
+) {
+    let Some(clocks) = clocks else {
+        return;
+    };
+
+    for (clock_text, mut text, mut color, mut transform) in text_query.iter_mut() {
+        let clock = clocks.get(clock_text.0);
+        let running = clock_text.0 == game_state.turn && game_state.result.is_none();
+
+        text.0 = format_clock(clock.remaining, clock.is_low());
+        color.set_if_neq(TextColor(match (clock.is_low(), running) {
+            (true, _) => Color::srgb(0.95, 0.25, 0.2),
+            (false, true) => Color::WHITE,
+            (false, false) => Color::srgb(0.55, 0.55, 0.55),
+        }));
+
+        let y = if clock_text.0 == orientation.bottom {
+            -OFFSET - TILE_SIZE / 4.0
+        } else {
+            OFFSET + TILE_SIZE / 4.0
+        };
+        transform.translation = Vec3::new(OFFSET, y, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_controls() {
+        let control = TimeControl::parse("40/90+30:30+30").unwrap();
+        assert_eq!(control.stages.len(), 2);
+        assert_eq!(control.stages[0].moves, Some(40));
+        assert_eq!(control.stages[0].base, Duration::from_secs(90 * 60));
+        assert_eq!(
+            control.stages[1].bonus,
+            TimeBonus::Increment(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn rejects_times_out_of_range() {
+        for spec in [
+            "-5", "nan", "inf", "1e30", "5+-3", "5dnan", "5+inf", "", "5x3",
+        ] {
+            assert_eq!(TimeControl::parse(spec), None, "{spec}");
+        }
+    }
+}
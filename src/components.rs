@@ -69,9 +69,27 @@ pub struct Selected;
 #[derive(Component)]
 pub struct SelectedFilter;
 
+// Shades the squares of a queued premove.
+#[derive(Component)]
+pub struct PremoveHighlight;
+
+// Shades the square under the mouse.
+#[derive(Component)]
+pub struct HoverHighlight;
+
+// A faded copy of the selected piece on the legal square under the mouse.
+#[derive(Component)]
+pub struct GhostPiece;
+
 #[derive(Component)]
 pub struct TurnText;
 
+#[derive(Component)]
+pub struct SidePanel;
+
+#[derive(Component)]
+pub struct BoardTexture;
+
 #[derive(Component)]
 pub struct MovedFilter;
 
@@ -79,4 +97,138 @@ pub struct MovedFilter;
 pub struct LegalMovesFilter;
 
 #[derive(Component)]
-pub struct InCheckHighlight;
\ No newline at end of file
+pub struct InCheckHighlight;
+
+// Attached to a piece while the player holds the mouse button on it.
+#[derive(Component)]
+pub struct Dragging {
+    // If the piece was already selected, dropping it back on its own square deselects it.
+    pub was_selected: bool,
+}
+
+// Glides a piece from one square to another.
+#[derive(Component)]
+pub struct MoveAnimation {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub timer: Timer,
+}
+
+// A captured piece that is no longer on the board, shrinking and fading out before it is despawned.
+#[derive(Component)]
+pub struct CaptureAnimation {
+    pub timer: Timer,
+}
+
+impl Default for CaptureAnimation {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(0.25, TimerMode::Once),
+        }
+    }
+}
+
+// A file letter or rank number drawn inside an edge square.
+#[derive(Component)]
+pub struct CoordinateLabel;
+
+#[derive(Component)]
+pub struct AnalysisText;
+
+// The dark background of the evaluation bar, which is Black's share.
+#[derive(Component)]
+pub struct EvalBar;
+
+// The light part of the evaluation bar, growing from the bottom with White's advantage.
+#[derive(Component)]
+pub struct EvalBarFill;
+
+// The scrollable list of moves played so far.
+#[derive(Component)]
+pub struct HistoryList;
+
+// A move in the history list. Clicking it shows the position after that many moves.
+#[derive(Component)]
+pub struct HistoryMoveButton(pub usize);
+
+// Returns from a past position to the live game.
+#[derive(Component)]
+pub struct LiveButton;
+
+// A piece of the past position on display. Unlike live pieces, it has no `Piece`.
+#[derive(Component)]
+pub struct HistoryPiece;
+
+// Last move and check highlights of the past position on display.
+#[derive(Component)]
+pub struct HistoryHighlight;
+
+// A small sprite of a captured piece, in the tray beside the board of the side that took it.
+#[derive(Component)]
+pub struct CapturedPieceIcon;
+
+// The "+N" material lead shown after the captured pieces of the side that is ahead.
+#[derive(Component)]
+pub struct MaterialText;
+
+// The remaining time of one side's clock.
+#[derive(Component)]
+pub struct ClockText(pub PieceColor);
+
+// Gives up the game for the human whose turn it is, or the human playing the engine.
+#[derive(Component)]
+pub struct ResignButton;
+
+// Offers a draw to the player over the network, or accepts their offer.
+#[derive(Component)]
+pub struct DrawButton;
+
+// The state of the network connection, on the main menu and beside the board.
+#[derive(Component)]
+pub struct NetStatusText;
+
+// The overlay announcing the result once the game is over.
+#[derive(Component)]
+pub struct GameOverDialog;
+
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub enum GameOverAction {
+    Rematch,
+    NewGame,
+    Export,
+    MainMenu,
+}
+
+// Tells where the exported game was written to.
+#[derive(Component)]
+pub struct ExportStatusText;
+
+// Buttons of the main menu and the game setup screen.
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub enum MenuAction {
+    NewGame,
+    Quit,
+    // The setup choices cycle through their options on each click.
+    Opponent,
+    Color,
+    TimeControl,
+    StartPosition,
+    Start,
+    Back,
+    Settings,
+}
+
+// The choices of the settings screen, which cycle through their options on each click like the
+// setup choices. The engine path is typed in.
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub enum SettingsAction {
+    Theme,
+    Volume,
+    Orientation,
+    LegalMoves,
+    Coordinates,
+    AnimationSpeed,
+    TimeControl,
+    EnginePath,
+    Back,
+}
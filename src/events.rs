@@ -1,8 +1,27 @@
 use bevy::prelude::*;
 
+use crate::components::PieceKind;
+
 #[derive(Event)]
 pub struct MoveMadeEvent {
     pub piece: Entity,
     pub start: (u8, u8),
     pub end: (u8, u8),
-}
\ No newline at end of file
+}
+
+// Asks for a move to be played on the board. Every source of moves (mouse, engine) goes through
+// this, so the move is validated and executed in one place.
+#[derive(Event)]
+pub struct MoveRequestedEvent {
+    pub start: (u8, u8),
+    pub end: (u8, u8),
+    // Piece a pawn turns into on the last rank. Defaults to a queen.
+    pub promotion: Option<PieceKind>,
+}
+
+// Clears the board for a new game. Every plugin resets its own part of the game.
+#[derive(Event)]
+pub struct NewGameEvent {
+    // Rematch: each player takes the other color.
+    pub swap_colors: bool,
+}
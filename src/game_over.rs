@@ -0,0 +1,277 @@
+use bevy::{prelude::*, ui::FocusPolicy};
+
+use crate::{
+    chess::{Position, is_dead_position},
+    components::{ExportStatusText, GameOverAction, GameOverDialog, PieceColor, ResignButton},
+    events::NewGameEvent,
+    pgn::{today, write_pgn},
+    resources::{
+        Annotations, GameEndReason, GameResult, GameState, MoveHistory, PlayerKind, Players,
+    },
+    states::{AppState, InGame},
+    uci::UciEngine,
+    ui::text_button,
+};
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::GameOver), spawn_dialog)
+            .add_systems(
+                Update,
+                (
+                    (detect_game_end_system, resign_button_system)
+                        .run_if(in_state(AppState::Playing)),
+                    game_over_state_system
+                        .after(detect_game_end_system)
+                        .after(resign_button_system),
+                    dialog_buttons_system.run_if(in_state(AppState::GameOver)),
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+// Checks the live position after every move for mate and the draw rules.
+fn detect_game_end_system(history: Res<MoveHistory>, mut game_state: ResMut<GameState>) {
+    if !history.is_changed() || game_state.result.is_some() {
+        return;
+    }
+    let positions: Vec<&Position> = (0..=history.moves.len())
+        .filter_map(|ply| history.position(ply))
+        .collect();
+    if let Some(result) = decided_result(&positions) {
+        game_state.result = Some(result);
+    }
+}
+
+/// The result the rules give the last of a game's positions, if it ends the game: mate, or a draw
+/// by insufficient material, the fifty-move rule or threefold repetition.
+pub fn decided_result(positions: &[&Position]) -> Option<GameResult> {
+    let position = positions.last()?;
+    let opponent = match position.turn {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    let repetition = position.repetition_key();
+    let repetitions = positions
+        .iter()
+        .filter(|earlier| earlier.repetition_key() == repetition)
+        .count();
+
+    let (winner, reason) = if position.is_stuck() {
+        match position.checked_king() {
+            Some(_) => (Some(opponent), GameEndReason::Checkmate),
+            None => (None, GameEndReason::Stalemate),
+        }
+    } else if is_dead_position(&position.board) {
+        (None, GameEndReason::InsufficientMaterial)
+    } else if position.halfmove_clock >= 100 {
+        (None, GameEndReason::FiftyMoveRule)
+    } else if repetitions >= 3 {
+        (None, GameEndReason::ThreefoldRepetition)
+    } else {
+        return None;
+    };
+    Some(GameResult { winner, reason })
+}
+
+// The human whose turn it is resigns. Against the engine it is always the human.
+fn resign_button_system(
+    button_query: Query<&Interaction, (Changed<Interaction>, With<ResignButton>)>,
+    players: Res<Players>,
+    mut game_state: ResMut<GameState>,
+) {
+    for interaction in button_query.iter() {
+        if *interaction != Interaction::Pressed
+            || game_state.result.is_some()
+            || players.is_observing()
+        {
+            continue;
+        }
+
+        let loser = if players.is_human(game_state.turn) {
+            game_state.turn
+        } else {
+            match game_state.turn {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White,
+            }
+        };
+        game_state.result = Some(GameResult {
+            winner: Some(match loser {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White,
+            }),
+            reason: GameEndReason::Resignation,
+        });
+    }
+}
+
+// The game is over as soon as it has a result. Taking back the last move resumes it.
+fn game_over_state_system(
+    game_state: Res<GameState>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    match (state.get(), game_state.result) {
+        (AppState::Playing, Some(_)) => next_state.set(AppState::GameOver),
+        (AppState::GameOver, None) => next_state.set(AppState::Playing),
+        _ => {}
+    }
+}
+
+fn spawn_dialog(mut commands: Commands, game_state: Res<GameState>) {
+    let Some(result) = game_state.result else {
+        return;
+    };
+
+    commands.spawn((
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        GlobalZIndex(10),
+        FocusPolicy::Block,
+        GameOverDialog,
+        DespawnOnExit(AppState::GameOver),
+        children![(
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(16.0),
+                padding: UiRect::all(Val::Px(30.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
+            children![
+                (
+                    Text::new("Game Over"),
+                    TextFont {
+                        font_size: 40.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                ),
+                (
+                    Text::new(result.describe()),
+                    TextFont {
+                        font_size: 28.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                ),
+                (
+                    Node {
+                        column_gap: Val::Px(12.0),
+                        ..default()
+                    },
+                    children![
+                        text_button("Rematch", GameOverAction::Rematch),
+                        text_button("New Game", GameOverAction::NewGame),
+                        text_button("Export PGN", GameOverAction::Export),
+                        text_button("Main Menu", GameOverAction::MainMenu),
+                    ],
+                ),
+                (
+                    Text::new(""),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.7, 0.7, 0.7)),
+                    ExportStatusText,
+                ),
+            ],
+        )],
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn dialog_buttons_system(
+    mut commands: Commands,
+    button_query: Query<(&Interaction, &GameOverAction), Changed<Interaction>>,
+    mut status_query: Query<&mut Text, With<ExportStatusText>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    history: Res<MoveHistory>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    engine: Option<Res<UciEngine>>,
+) {
+    for (interaction, action) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match action {
+            GameOverAction::Rematch => {
+                commands.trigger(NewGameEvent { swap_colors: true });
+                next_state.set(AppState::Playing);
+            }
+            GameOverAction::NewGame => next_state.set(AppState::GameSetup),
+            GameOverAction::MainMenu => next_state.set(AppState::MainMenu),
+            GameOverAction::Export => {
+                let status = match export_game(&history, &game_state, &players, engine.as_deref()) {
+                    Ok(path) => format!("Saved to {path}"),
+                    Err(err) => format!("Could not save the game: {err}"),
+                };
+                for mut text in status_query.iter_mut() {
+                    **text = status.clone();
+                }
+            }
+        }
+    }
+}
+
+// Writes the game to a new PGN file in the working directory and returns its name.
+fn export_game(
+    history: &MoveHistory,
+    game_state: &GameState,
+    players: &Players,
+    engine: Option<&UciEngine>,
+) -> std::io::Result<String> {
+    let name = |color: PieceColor| match players.get(color) {
+        PlayerKind::Human => "Human".to_string(),
+        PlayerKind::Engine => engine
+            .and_then(|engine| engine.name.clone())
+            .unwrap_or_else(|| "Engine".to_string()),
+        PlayerKind::Remote => "Opponent".to_string(),
+    };
+    let result = game_state.result.map_or("*", |result| result.pgn());
+    let termination = game_state
+        .result
+        .map_or("unterminated", |result| result.reason.termination());
+
+    let tags = [
+        ("Event", "Casual game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", today()),
+        ("Round", "-".to_string()),
+        ("White", name(PieceColor::White)),
+        ("Black", name(PieceColor::Black)),
+        ("Result", result.to_string()),
+        ("Termination", termination.to_string()),
+    ];
+    let moves: Vec<&str> = history
+        .moves
+        .iter()
+        .map(|record| record.san.as_str())
+        .collect();
+    let annotations: Vec<&Annotations> = std::iter::once(&history.start_annotations)
+        .chain(history.moves.iter().map(|record| &record.annotations))
+        .collect();
+    let pgn = write_pgn(&tags, history.start.as_ref(), &moves, &annotations, result);
+
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let path = format!("game-{seconds}.pgn");
+    std::fs::write(&path, pgn)?;
+    Ok(path)
+}
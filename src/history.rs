@@ -0,0 +1,380 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    ui::RelativeCursorPosition,
+};
+
+use crate::{
+    board::{piece_bundle, piece_sprite},
+    components::{
+        CaptureAnimation, HistoryHighlight, HistoryList, HistoryMoveButton, HistoryPiece,
+        InCheckHighlight, LegalMovesFilter, LiveButton, MovedFilter, Piece, PieceColor,
+        PremoveHighlight, SelectedFilter,
+    },
+    resources::{BoardOrientation, GameState, MoveHistory, Players},
+    states::InGame,
+    systems::{check_highlight, last_move_highlight},
+    theme::Theme,
+};
+
+const MOVE_BUTTON_COLOR: Color = Color::NONE;
+const VIEWED_MOVE_BUTTON_COLOR: Color = Color::srgb(0.3, 0.45, 0.25);
+const LINE_HEIGHT: f32 = 30.0;
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MoveHistory>().add_systems(
+            Update,
+            (
+                undo_redo_system,
+                history_keys_system,
+                history_buttons_system,
+                scroll_history_system,
+                update_history_list_system
+                    .after(undo_redo_system)
+                    .after(history_keys_system)
+                    .after(history_buttons_system),
+                show_past_position_system
+                    .after(undo_redo_system)
+                    .after(history_keys_system)
+                    .after(history_buttons_system),
+                hide_live_board_system,
+            )
+                .run_if(in_state(InGame)),
+        );
+    }
+}
+
+// Ctrl+Z takes back the last move and Ctrl+Y (or Ctrl+Shift+Z) plays it again. Against the engine
+// its reply goes along with it, so it is the human's turn again.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn undo_redo_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<MoveHistory>,
+    mut game_state: ResMut<GameState>,
+    players: Res<Players>,
+    orientation: Res<BoardOrientation>,
+    live_query: Query<
+        Entity,
+        Or<(
+            With<Piece>,
+            With<SelectedFilter>,
+            With<MovedFilter>,
+            With<LegalMovesFilter>,
+            With<InCheckHighlight>,
+        )>,
+    >,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+) {
+    // Moves played over the network stay played.
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) || players.has_remote() {
+        return;
+    }
+    // A game lost on time or by resignation stays lost, whatever the board looked like.
+    if game_state
+        .result
+        .is_some_and(|result| result.reason.is_off_the_board())
+    {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let undo = keys.just_pressed(KeyCode::KeyZ) && !shift;
+    let redo = keys.just_pressed(KeyCode::KeyY) || (keys.just_pressed(KeyCode::KeyZ) && shift);
+
+    let mut changed = false;
+    for _ in 0..2 {
+        let record = if undo {
+            history.moves.pop()
+        } else if redo {
+            history.redo.pop()
+        } else {
+            None
+        };
+        let Some(record) = record else {
+            break;
+        };
+        if undo {
+            history.redo.push(record);
+        } else {
+            history.moves.push(record);
+        }
+        changed = true;
+
+        if history
+            .current()
+            .is_none_or(|position| players.is_human(position.turn))
+        {
+            break;
+        }
+    }
+    if !changed {
+        return;
+    }
+    let Some(position) = history.current().cloned() else {
+        return;
+    };
+    history.viewing = None;
+
+    game_state.turn = position.turn;
+    game_state.en_passant_target = position.en_passant;
+    game_state.halfmove_clock = position.halfmove_clock;
+    game_state.fullmove_number = position.fullmove_number;
+    // Any result left was decided by the old position.
+    game_state.result = None;
+
+    // Rebuild the board from the snapshot. This brings back captured pieces, castled rooks and
+    // promoted pawns as they were.
+    for entity in live_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for (piece, square) in position.board.iter() {
+        commands.spawn(piece_bundle(
+            *piece,
+            *square,
+            &asset_server,
+            &orientation,
+            &theme,
+        ));
+    }
+    if let Some(record) = history.moves.last() {
+        commands.spawn((
+            last_move_highlight(record.start, &orientation, &theme),
+            MovedFilter,
+        ));
+        commands.spawn((
+            last_move_highlight(record.end, &orientation, &theme),
+            MovedFilter,
+        ));
+    }
+    if let Some(king) = position.checked_king() {
+        commands.spawn((
+            check_highlight(king, &orientation, &asset_server, &theme),
+            InCheckHighlight,
+        ));
+    }
+}
+
+// Shift+Left and Shift+Right step through the game, Home jumps to the start and End back to the
+// live position. The arrow keys alone move the keyboard cursor on the board.
+fn history_keys_system(keys: Res<ButtonInput<KeyCode>>, mut history: ResMut<MoveHistory>) {
+    if history.moves.is_empty() {
+        return;
+    }
+    let current = history.viewing.unwrap_or(history.moves.len());
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if shift && keys.just_pressed(KeyCode::ArrowLeft) {
+        history.view(current.saturating_sub(1));
+    } else if shift && keys.just_pressed(KeyCode::ArrowRight) {
+        history.view(current + 1);
+    } else if keys.just_pressed(KeyCode::Home) {
+        history.view(0);
+    } else if keys.just_pressed(KeyCode::End) {
+        history.viewing = None;
+    }
+}
+
+fn history_buttons_system(
+    move_button_query: Query<(&Interaction, &HistoryMoveButton), Changed<Interaction>>,
+    live_button_query: Query<&Interaction, (Changed<Interaction>, With<LiveButton>)>,
+    mut history: ResMut<MoveHistory>,
+) {
+    for (interaction, button) in move_button_query.iter() {
+        if *interaction == Interaction::Pressed {
+            history.view(button.0);
+        }
+    }
+    for interaction in live_button_query.iter() {
+        if *interaction == Interaction::Pressed {
+            history.viewing = None;
+        }
+    }
+}
+
+fn scroll_history_system(
+    mut mouse_wheel: MessageReader<MouseWheel>,
+    mut list_query: Query<(&RelativeCursorPosition, &mut ScrollPosition), With<HistoryList>>,
+) {
+    for event in mouse_wheel.read() {
+        let dy = match event.unit {
+            MouseScrollUnit::Line => event.y * LINE_HEIGHT,
+            MouseScrollUnit::Pixel => event.y,
+        };
+        for (cursor, mut scroll) in list_query.iter_mut() {
+            if cursor.cursor_over() {
+                scroll.y = (scroll.y - dy).max(0.0);
+            }
+        }
+    }
+}
+
+// Rebuilds the list as numbered pairs of moves, marking the one whose position is on display.
+fn update_history_list_system(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    mut list_query: Query<(Entity, &mut ScrollPosition), With<HistoryList>>,
+    mut live_button_query: Query<&mut Visibility, With<LiveButton>>,
+    mut shown_moves: Local<usize>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+    let Ok((list, mut scroll)) = list_query.single_mut() else {
+        return;
+    };
+    let viewed = history.viewing.unwrap_or(history.moves.len());
+
+    commands.entity(list).despawn_children();
+    commands.entity(list).with_children(|parent| {
+        for (i, record) in history.moves.iter().enumerate() {
+            let Some(before) = history.position(i) else {
+                continue;
+            };
+
+            // A new row starts with each White move, or a Black move at the very start.
+            if before.turn == PieceColor::White || i == 0 {
+                let number = match before.turn {
+                    PieceColor::White => format!("{}.", before.fullmove_number),
+                    PieceColor::Black => format!("{}...", before.fullmove_number),
+                };
+                parent.spawn((
+                    Node {
+                        width: Val::Px(60.0),
+                        ..default()
+                    },
+                    Text::new(number),
+                    TextFont {
+                        font_size: 22.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.7, 0.7, 0.7)),
+                ));
+            }
+
+            let ply = i + 1;
+            parent.spawn((
+                Button,
+                Node {
+                    width: Val::Px(100.0),
+                    padding: UiRect::horizontal(Val::Px(6.0)),
+                    ..default()
+                },
+                BackgroundColor(if ply == viewed {
+                    VIEWED_MOVE_BUTTON_COLOR
+                } else {
+                    MOVE_BUTTON_COLOR
+                }),
+                HistoryMoveButton(ply),
+                children![(
+                    Text::new(record.san.clone()),
+                    TextFont {
+                        font_size: 22.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                )],
+            ));
+        }
+    });
+
+    // Follow the game as it goes on. The layout clamps this to the end of the list.
+    if *shown_moves != history.moves.len() && !history.is_browsing() {
+        scroll.y = f32::MAX;
+    }
+    *shown_moves = history.moves.len();
+
+    for mut visibility in live_button_query.iter_mut() {
+        visibility.set_if_neq(if history.is_browsing() {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
+
+// Draws the past position on display with its own entities, leaving the live board untouched.
+#[allow(clippy::type_complexity)]
+fn show_past_position_system(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    orientation: Res<BoardOrientation>,
+    shown_query: Query<Entity, Or<(With<HistoryPiece>, With<HistoryHighlight>)>>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+) {
+    if !history.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for entity in shown_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let Some(ply) = history.viewing else {
+        return;
+    };
+    let Some(position) = history.position(ply) else {
+        return;
+    };
+
+    for (piece, square) in position.board.iter() {
+        commands.spawn((
+            piece_sprite(
+                piece.color,
+                piece.kind,
+                (square.x, square.y),
+                &asset_server,
+                &orientation,
+                &theme,
+            ),
+            *square,
+            HistoryPiece,
+        ));
+    }
+    if let Some(record) = ply.checked_sub(1).and_then(|i| history.moves.get(i)) {
+        commands.spawn((
+            last_move_highlight(record.start, &orientation, &theme),
+            HistoryHighlight,
+        ));
+        commands.spawn((
+            last_move_highlight(record.end, &orientation, &theme),
+            HistoryHighlight,
+        ));
+    }
+    if let Some(king) = position.checked_king() {
+        commands.spawn((
+            check_highlight(king, &orientation, &asset_server, &theme),
+            HistoryHighlight,
+        ));
+    }
+}
+
+// Live pieces and highlights stay in place while browsing, just out of sight.
+#[allow(clippy::type_complexity)]
+fn hide_live_board_system(
+    history: Res<MoveHistory>,
+    mut live_query: Query<
+        &mut Visibility,
+        Or<(
+            With<Piece>,
+            With<SelectedFilter>,
+            With<MovedFilter>,
+            With<LegalMovesFilter>,
+            With<InCheckHighlight>,
+            With<CaptureAnimation>,
+            With<PremoveHighlight>,
+        )>,
+    >,
+) {
+    let visibility = if history.is_browsing() {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    for mut live in live_query.iter_mut() {
+        live.set_if_neq(visibility);
+    }
+}
@@ -0,0 +1,227 @@
+// Feedback before a click: the square under the mouse is shaded, the cursor turns into a hand over
+// pieces that can move, and with a piece selected, hovering one of its legal squares shows the piece
+// there and fades out what it would capture.
+
+use bevy::{
+    prelude::*,
+    window::{CursorIcon, PrimaryWindow, SystemCursorIcon},
+};
+
+use crate::{
+    board::{PIECE_SCALE, TILE_SIZE, get_board_square, get_world_position, piece_image_path},
+    chess::{en_passant_capture_square, get_legal_moves},
+    components::{Dragging, GhostPiece, HoverHighlight, Piece, Square},
+    resources::{BoardOrientation, GameState, LegalMoves, MoveHistory, Players},
+    states::{AppState, InGame},
+    systems::cursor_world_position,
+    theme::Theme,
+};
+
+// How much of a piece that would be captured still shows.
+const CAPTURED_ALPHA: f32 = 0.35;
+const GHOST_ALPHA: f32 = 0.5;
+
+pub struct HoverPlugin;
+
+impl Plugin for HoverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(InGame), spawn_hover_sprites)
+            .add_systems(OnExit(AppState::Playing), clear_hover)
+            .add_systems(
+                Update,
+                (
+                    hover_square_system,
+                    hover_cursor_system,
+                    move_preview_system,
+                )
+                    .run_if(in_state(AppState::Playing)),
+            );
+    }
+}
+
+fn spawn_hover_sprites(mut commands: Commands) {
+    commands.spawn((
+        Sprite::from_color(Color::NONE, Vec2::splat(TILE_SIZE)),
+        Transform::default(),
+        Visibility::Hidden,
+        HoverHighlight,
+        DespawnOnExit(InGame),
+    ));
+    commands.spawn((
+        Sprite::default(),
+        Transform::from_scale(Vec3::splat(PIECE_SCALE)),
+        Visibility::Hidden,
+        GhostPiece,
+        DespawnOnExit(InGame),
+    ));
+}
+
+// The square under the mouse, unless a past position is on display.
+fn hovered_square(
+    window_query: &Query<&Window, With<PrimaryWindow>>,
+    camera_query: &Query<(&Camera, &GlobalTransform)>,
+    orientation: &BoardOrientation,
+    history: &MoveHistory,
+) -> Option<(u8, u8)> {
+    if history.is_browsing() {
+        return None;
+    }
+    cursor_world_position(window_query, camera_query)
+        .and_then(|position| get_board_square(position, orientation))
+}
+
+fn hover_square_system(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    theme: Res<Theme>,
+    mut highlight_query: Query<
+        (&mut Sprite, &mut Transform, &mut Visibility),
+        With<HoverHighlight>,
+    >,
+) {
+    let square = hovered_square(&window_query, &camera_query, &orientation, &history);
+    for (mut sprite, mut transform, mut visibility) in highlight_query.iter_mut() {
+        let Some((x, y)) = square else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        sprite.color = theme.hover;
+        transform.translation = get_world_position(x as usize, y as usize, 0.6, &orientation);
+        visibility.set_if_neq(Visibility::Inherited);
+    }
+}
+
+// A hand over the pieces of a human to move that have a legal move, and a closed one while
+// dragging.
+#[allow(clippy::too_many_arguments)]
+fn hover_cursor_system(
+    mut commands: Commands,
+    window_entity_query: Query<Entity, With<PrimaryWindow>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    piece_query: Query<(&Piece, &Square)>,
+    dragging_query: Query<(), With<Dragging>>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    mut shown: Local<Option<SystemCursorIcon>>,
+) {
+    let Ok(window) = window_entity_query.single() else {
+        return;
+    };
+
+    let movable = |square: (u8, u8)| {
+        let board: Vec<(Piece, Square)> = piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+        board.iter().any(|(piece, s)| {
+            (s.x, s.y) == square
+                && piece.color == game_state.turn
+                && !get_legal_moves(piece, square, &board, game_state.en_passant_target).is_empty()
+        })
+    };
+    let icon = if !dragging_query.is_empty() {
+        SystemCursorIcon::Grabbing
+    } else if players.is_human(game_state.turn)
+        && game_state.result.is_none()
+        && hovered_square(&window_query, &camera_query, &orientation, &history).is_some_and(movable)
+    {
+        SystemCursorIcon::Pointer
+    } else {
+        SystemCursorIcon::Default
+    };
+
+    if *shown != Some(icon) {
+        commands.entity(window).insert(CursorIcon::from(icon));
+        *shown = Some(icon);
+    }
+}
+
+// Once the game is over, or left, nothing responds to the mouse anymore.
+#[allow(clippy::type_complexity)]
+fn clear_hover(
+    mut commands: Commands,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+    mut piece_query: Query<&mut Sprite, With<Piece>>,
+    mut hover_query: Query<&mut Visibility, Or<(With<HoverHighlight>, With<GhostPiece>)>>,
+) {
+    if let Ok(window) = window_query.single() {
+        commands
+            .entity(window)
+            .insert(CursorIcon::from(SystemCursorIcon::Default));
+    }
+    for mut sprite in piece_query.iter_mut() {
+        sprite.color.set_alpha(1.0);
+    }
+    for mut visibility in hover_query.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+// The selected piece on the hovered square, if it can go there, and the piece it would take faded
+// out. The legal squares are the ones `highlight_legal_moves_system` found.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn move_preview_system(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    legal_moves: Res<LegalMoves>,
+    game_state: Res<GameState>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    theme: Res<Theme>,
+    asset_server: Res<AssetServer>,
+    mut piece_query: Query<(Entity, &Piece, &Square, &mut Sprite, Has<Dragging>)>,
+    mut ghost_query: Query<
+        (&mut Sprite, &mut Transform, &mut Visibility),
+        (With<GhostPiece>, Without<Piece>),
+    >,
+) {
+    let target = hovered_square(&window_query, &camera_query, &orientation, &history)
+        .filter(|square| legal_moves.squares.contains(square));
+    let preview = legal_moves.piece.zip(target).and_then(|(entity, target)| {
+        piece_query
+            .get(entity)
+            .ok()
+            .map(|(_, piece, square, _, dragging)| (*piece, (square.x, square.y), target, dragging))
+    });
+
+    // The captured piece is on the target square, except for en passant.
+    let captured = preview.and_then(|(piece, start, end, _)| {
+        let board: Vec<(Piece, Square)> =
+            piece_query.iter().map(|(_, p, s, _, _)| (*p, *s)).collect();
+        let square =
+            en_passant_capture_square(&piece, start, end, &board, game_state.en_passant_target)
+                .unwrap_or(end);
+        piece_query
+            .iter()
+            .find(|(_, other, s, _, _)| (s.x, s.y) == square && other.color != piece.color)
+            .map(|(entity, _, _, _, _)| entity)
+    });
+    for (entity, _, _, mut sprite, _) in piece_query.iter_mut() {
+        let alpha = if Some(entity) == captured {
+            CAPTURED_ALPHA
+        } else {
+            1.0
+        };
+        if sprite.color.alpha() != alpha {
+            sprite.color.set_alpha(alpha);
+        }
+    }
+
+    for (mut sprite, mut transform, mut visibility) in ghost_query.iter_mut() {
+        // A dragged piece already shows where it would go.
+        let Some((piece, _, (x, y), false)) = preview else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        let image = asset_server.load(piece_image_path(&theme, piece.color, piece.kind));
+        if sprite.image != image {
+            sprite.image = image;
+        }
+        sprite.color = Color::WHITE.with_alpha(GHOST_ALPHA);
+        transform.translation = get_world_position(x as usize, y as usize, 1.5, &orientation);
+        visibility.set_if_neq(Visibility::Inherited);
+    }
+}
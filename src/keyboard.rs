@@ -0,0 +1,221 @@
+// Playing without a mouse. The arrow keys move a cursor over the board, Enter or Space acts like a
+// click on its square and Escape drops the selection and any premoves.
+
+use bevy::prelude::*;
+
+use crate::{
+    board::{TILE_SIZE, get_world_position},
+    components::{Piece, PieceColor, Selected, Square},
+    premove::Premoves,
+    resources::{BoardOrientation, GameState, MoveHistory, Players},
+    states::AppState,
+    systems::activate_square,
+    theme::Theme,
+};
+
+pub struct KeyboardPlugin;
+
+impl Plugin for KeyboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeyboardCursor>().add_systems(
+            Update,
+            (
+                move_cursor_system,
+                activate_cursor_system.after(move_cursor_system),
+                deselect_system,
+                draw_cursor_system.after(move_cursor_system),
+            )
+                .run_if(in_state(AppState::Playing)),
+        );
+    }
+}
+
+// The square under the keyboard cursor. It only shows up once the keyboard is used and goes away
+// again on a mouse click.
+#[derive(Resource, Default)]
+struct KeyboardCursor {
+    square: Option<(u8, u8)>,
+}
+
+// The cursor first appears in front of the king of the side at the bottom.
+fn first_square(orientation: &BoardOrientation) -> (u8, u8) {
+    if orientation.is_flipped() {
+        (4, 6)
+    } else {
+        (4, 1)
+    }
+}
+
+// Up always goes towards the top of the screen, whichever side is at the bottom.
+fn move_cursor_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    orientation: Res<BoardOrientation>,
+    mut cursor: ResMut<KeyboardCursor>,
+) {
+    if mouse_input.get_just_pressed().next().is_some() {
+        cursor.square = None;
+        return;
+    }
+    // Shift with the arrow keys steps through the move history instead.
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        return;
+    }
+
+    let (dx, dy) = if keys.just_pressed(KeyCode::ArrowLeft) {
+        (-1, 0)
+    } else if keys.just_pressed(KeyCode::ArrowRight) {
+        (1, 0)
+    } else if keys.just_pressed(KeyCode::ArrowUp) {
+        (0, 1)
+    } else if keys.just_pressed(KeyCode::ArrowDown) {
+        (0, -1)
+    } else {
+        return;
+    };
+    let (dx, dy) = if orientation.is_flipped() {
+        (-dx, -dy)
+    } else {
+        (dx, dy)
+    };
+
+    cursor.square = Some(match cursor.square {
+        None => first_square(&orientation),
+        Some((x, y)) => (
+            (x as i8 + dx).clamp(0, 7) as u8,
+            (y as i8 + dy).clamp(0, 7) as u8,
+        ),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn activate_cursor_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut cursor: ResMut<KeyboardCursor>,
+    piece_query: Query<(Entity, &Piece, &Square)>,
+    selected_piece_query: Query<Entity, With<Selected>>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    mut premoves: ResMut<Premoves>,
+) {
+    if !keys.any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter, KeyCode::Space]) {
+        return;
+    }
+    let Some(square) = cursor.square else {
+        cursor.square = Some(first_square(&orientation));
+        return;
+    };
+
+    // The same rules as for the mouse in `input_system`.
+    if history.is_browsing() || game_state.result.is_some() {
+        return;
+    }
+    let opponent = match game_state.turn {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    if players.is_human(game_state.turn) {
+        activate_square(
+            &mut commands,
+            square,
+            &piece_query,
+            &selected_piece_query,
+            &game_state,
+            false,
+        );
+    } else if players.is_human(opponent) {
+        let board: Vec<(Piece, Square)> = piece_query.iter().map(|(_, p, s)| (*p, *s)).collect();
+        premoves.click(square, &board, opponent);
+    }
+}
+
+// Escape also takes back all premoves.
+fn deselect_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    selected_query: Query<Entity, With<Selected>>,
+    mut premoves: ResMut<Premoves>,
+) {
+    if !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    for entity in selected_query.iter() {
+        commands.entity(entity).remove::<Selected>();
+    }
+    premoves.clear();
+}
+
+fn draw_cursor_system(
+    mut gizmos: Gizmos,
+    cursor: Res<KeyboardCursor>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    theme: Res<Theme>,
+) {
+    let Some((x, y)) = cursor.square else {
+        return;
+    };
+    if history.is_browsing() {
+        return;
+    }
+
+    let center = get_world_position(x as usize, y as usize, 0.0, &orientation).truncate();
+    gizmos.rect_2d(
+        Isometry2d::from_translation(center),
+        Vec2::splat(TILE_SIZE - 8.0),
+        theme.focus,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::PieceColor;
+
+    fn press(app: &mut App, key: KeyCode) -> Option<(u8, u8)> {
+        let mut keys = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+        keys.clear();
+        keys.release_all();
+        keys.press(key);
+        app.update();
+        app.world().resource::<KeyboardCursor>().square
+    }
+
+    fn cursor_app(bottom: PieceColor) -> App {
+        let mut app = App::new();
+        app.init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<ButtonInput<MouseButton>>()
+            .init_resource::<KeyboardCursor>()
+            .insert_resource(BoardOrientation {
+                bottom,
+                auto_flip: false,
+            })
+            .add_systems(Update, move_cursor_system);
+        app
+    }
+
+    #[test]
+    fn up_points_to_the_top_of_the_screen() {
+        let mut app = cursor_app(PieceColor::White);
+        assert_eq!(press(&mut app, KeyCode::ArrowUp), Some((4, 1)));
+        assert_eq!(press(&mut app, KeyCode::ArrowUp), Some((4, 2)));
+        assert_eq!(press(&mut app, KeyCode::ArrowLeft), Some((3, 2)));
+
+        let mut app = cursor_app(PieceColor::Black);
+        assert_eq!(press(&mut app, KeyCode::ArrowUp), Some((4, 6)));
+        assert_eq!(press(&mut app, KeyCode::ArrowUp), Some((4, 5)));
+        assert_eq!(press(&mut app, KeyCode::ArrowLeft), Some((5, 5)));
+    }
+
+    #[test]
+    fn the_cursor_stays_on_the_board() {
+        let mut app = cursor_app(PieceColor::White);
+        for _ in 0..10 {
+            press(&mut app, KeyCode::ArrowDown);
+        }
+        assert_eq!(press(&mut app, KeyCode::ArrowDown), Some((4, 0)));
+    }
+}
@@ -1,6 +1,32 @@
-use bevy::{prelude::*, window::WindowMode};
+use bevy::{
+    prelude::*,
+    window::{WindowMode, WindowResizeConstraints},
+};
 
-use crate::{board::BoardPlugin, resources::GameState, systems::GamePlugin, ui::UIPlugin};
+use crate::{
+    analysis::AnalysisPlugin,
+    annotations::AnnotationPlugin,
+    animation::AnimationPlugin,
+    board::BoardPlugin,
+    captures::CapturesPlugin,
+    clock::ClockPlugin,
+    game_over::GameOverPlugin,
+    history::HistoryPlugin,
+    hover::HoverPlugin,
+    keyboard::KeyboardPlugin,
+    menu::MenuPlugin,
+    net::{NetConfig, NetPlugin},
+    premove::PremovePlugin,
+    resources::{BoardOrientation, DisplayOptions, GameSetup, GameState, Players, SoundSettings},
+    server::ServerConfig,
+    settings::{SettingsPlugin, UserSettings},
+    states::{AppState, InGame},
+    sound::SoundPlugin,
+    systems::GamePlugin,
+    theme::ThemePlugin,
+    uci::{UciConfig, UciPlugin},
+    ui::UIPlugin,
+};
 
 mod board;
 mod components;
@@ -9,21 +35,86 @@ mod systems;
 mod ui;
 mod events;
 mod chess;
+mod uci;
+mod analysis;
+mod animation;
+mod history;
+mod captures;
+mod clock;
+mod game_over;
+mod pgn;
+mod states;
+mod menu;
+mod theme;
+mod sound;
+mod keyboard;
+mod premove;
+mod annotations;
+mod hover;
+mod settings;
+mod net;
+mod server;
 
 fn main() {
+    if let Some(config) = ServerConfig::from_args(std::env::args().skip(1)) {
+        server::run(config);
+        return;
+    }
+
+    let settings = UserSettings::load();
+    let uci_config = UciConfig::from_args(std::env::args().skip(1), &settings);
+
     App::new()
         .init_resource::<GameState>()
+        .init_resource::<Players>()
+        .insert_resource(DisplayOptions::from_settings(&settings))
+        .insert_resource(BoardOrientation::from_args(std::env::args().skip(1)))
+        .insert_resource(GameSetup::from_args(
+            std::env::args().skip(1),
+            &uci_config,
+            &settings,
+        ))
+        .insert_resource(SoundSettings::from_args(
+            std::env::args().skip(1),
+            &settings,
+        ))
+        .insert_resource(uci_config)
+        .insert_resource(NetConfig::from_args(std::env::args().skip(1)))
+        .insert_resource(settings)
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Chess".into(),
                 mode: WindowMode::Windowed,
                 resolution: (1600, 900).into(),
+                resize_constraints: WindowResizeConstraints {
+                    min_width: 800.0,
+                    min_height: 500.0,
+                    ..default()
+                },
                 ..default()
             }),
             ..default()
         }))
+        .init_state::<AppState>()
+        .add_computed_state::<InGame>()
+        .add_plugins(MenuPlugin)
+        .add_plugins(SettingsPlugin)
+        .add_plugins(ThemePlugin)
         .add_plugins(UIPlugin)
         .add_plugins(BoardPlugin)
         .add_plugins(GamePlugin)
+        .add_plugins(KeyboardPlugin)
+        .add_plugins(PremovePlugin)
+        .add_plugins(AnnotationPlugin)
+        .add_plugins(HoverPlugin)
+        .add_plugins(AnimationPlugin)
+        .add_plugins(HistoryPlugin)
+        .add_plugins(CapturesPlugin)
+        .add_plugins(ClockPlugin)
+        .add_plugins(GameOverPlugin)
+        .add_plugins(SoundPlugin)
+        .add_plugins(UciPlugin)
+        .add_plugins(NetPlugin)
+        .add_plugins(AnalysisPlugin)
         .run();
 }
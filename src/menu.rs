@@ -0,0 +1,243 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{MenuAction, NetStatusText, PieceColor},
+    events::NewGameEvent,
+    net::NetConnection,
+    resources::{BoardOrientation, GameSetup, PlayerKind, Players},
+    states::{AppState, InGame},
+    uci::UciEngine,
+    ui::text_button,
+};
+
+// Offered on the setup screen after "Untimed". See `TimeControl::parse`.
+pub const TIME_CONTROLS: [&str; 6] = ["1+0", "3+2", "5+3", "15+10", "90+30", "40/90+30:30+30"];
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::MainMenu), spawn_main_menu)
+            .add_systems(OnEnter(AppState::GameSetup), spawn_game_setup)
+            .add_systems(OnEnter(InGame), start_game)
+            .add_systems(
+                Update,
+                (
+                    menu_buttons_system,
+                    update_setup_text_system.after(menu_buttons_system),
+                )
+                    .run_if(not(in_state(InGame))),
+            );
+    }
+}
+
+fn menu_screen() -> Node {
+    Node {
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
+        flex_direction: FlexDirection::Column,
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        row_gap: Val::Px(20.0),
+        ..default()
+    }
+}
+
+fn title(text: &str) -> impl Bundle {
+    (
+        Text::new(text),
+        TextFont {
+            font_size: 64.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+    )
+}
+
+fn spawn_main_menu(mut commands: Commands) {
+    commands.spawn((
+        menu_screen(),
+        DespawnOnExit(AppState::MainMenu),
+        children![
+            title("Chess"),
+            text_button("New Game", MenuAction::NewGame),
+            text_button("Settings", MenuAction::Settings),
+            text_button("Quit", MenuAction::Quit),
+            (
+                Text::new(""),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.7, 0.7, 0.7)),
+                NetStatusText,
+            ),
+        ],
+    ));
+}
+
+// The choices show their current option as their label, see `update_setup_text_system`.
+fn spawn_game_setup(mut commands: Commands) {
+    commands.spawn((
+        menu_screen(),
+        DespawnOnExit(AppState::GameSetup),
+        children![
+            title("New Game"),
+            text_button("", MenuAction::Opponent),
+            text_button("", MenuAction::Color),
+            text_button("", MenuAction::TimeControl),
+            text_button("", MenuAction::StartPosition),
+            (
+                Node {
+                    column_gap: Val::Px(12.0),
+                    ..default()
+                },
+                children![
+                    text_button("Start", MenuAction::Start),
+                    text_button("Back", MenuAction::Back),
+                ],
+            ),
+        ],
+    ));
+}
+
+fn menu_buttons_system(
+    button_query: Query<(&Interaction, &MenuAction), Changed<Interaction>>,
+    mut setup: ResMut<GameSetup>,
+    engine: Option<Res<UciEngine>>,
+    connection: Option<Res<NetConnection>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    // Only the host starts games over the network.
+    let remote = connection.is_some_and(|connection| connection.can_host_game());
+
+    for (interaction, action) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match action {
+            MenuAction::NewGame => next_state.set(AppState::GameSetup),
+            MenuAction::Settings => next_state.set(AppState::Settings),
+            MenuAction::Quit => {
+                exit.write(AppExit::Success);
+            }
+            // Playing the engine needs one to be running, and playing over the network a
+            // connection.
+            MenuAction::Opponent => {
+                setup.opponent = match setup.opponent {
+                    PlayerKind::Human if engine.is_some() => PlayerKind::Engine,
+                    PlayerKind::Human | PlayerKind::Engine if remote => PlayerKind::Remote,
+                    _ => PlayerKind::Human,
+                };
+            }
+            MenuAction::Color => {
+                setup.color = match setup.color {
+                    Some(PieceColor::White) => Some(PieceColor::Black),
+                    Some(PieceColor::Black) => None,
+                    None => Some(PieceColor::White),
+                };
+            }
+            MenuAction::TimeControl => {
+                let mut options: Vec<Option<String>> = vec![None];
+                options.extend(TIME_CONTROLS.iter().map(|spec| Some(spec.to_string())));
+                if !options.contains(&setup.time_control) {
+                    options.push(setup.time_control.clone());
+                }
+                let current = options
+                    .iter()
+                    .position(|option| *option == setup.time_control)
+                    .unwrap_or(0);
+                setup.time_control = options[(current + 1) % options.len()].clone();
+            }
+            MenuAction::StartPosition => {
+                setup.start_fen = match setup.start_fen {
+                    None => setup.custom_fen.clone(),
+                    Some(_) => None,
+                };
+            }
+            MenuAction::Start => next_state.set(AppState::Playing),
+            MenuAction::Back => next_state.set(AppState::MainMenu),
+        }
+    }
+}
+
+fn update_setup_text_system(
+    setup: Res<GameSetup>,
+    engine: Option<Res<UciEngine>>,
+    button_query: Query<(&MenuAction, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    for (action, children) in button_query.iter() {
+        let color = match setup.color {
+            Some(PieceColor::White) => "White",
+            Some(PieceColor::Black) => "Black",
+            None => "Random",
+        };
+        let label = match action {
+            MenuAction::Opponent => match (setup.opponent, &engine) {
+                (PlayerKind::Human, _) => "Opponent: Human".to_string(),
+                (PlayerKind::Engine, Some(engine)) => format!(
+                    "Opponent: {}",
+                    engine.name.as_deref().unwrap_or("Engine")
+                ),
+                (PlayerKind::Engine, None) => "Opponent: Engine".to_string(),
+                (PlayerKind::Remote, _) => "Opponent: Network".to_string(),
+            },
+            // Between two humans the color decides which side is at the bottom.
+            MenuAction::Color => match setup.opponent {
+                PlayerKind::Engine | PlayerKind::Remote => format!("You play: {color}"),
+                PlayerKind::Human => format!("Bottom side: {color}"),
+            },
+            MenuAction::TimeControl => format!(
+                "Time control: {}",
+                setup.time_control.as_deref().unwrap_or("Untimed")
+            ),
+            MenuAction::StartPosition => match setup.start_fen {
+                None => "Start position: Standard".to_string(),
+                Some(_) => "Start position: From FEN".to_string(),
+            },
+            _ => continue,
+        };
+
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child)
+                && text.0 != label
+            {
+                text.0 = label.clone();
+            }
+        }
+    }
+}
+
+// Seats the players as chosen on the setup screen and sets up the board. A spectator seats remote
+// players on both sides.
+pub fn start_game(
+    mut commands: Commands,
+    setup: Res<GameSetup>,
+    mut players: ResMut<Players>,
+    mut orientation: ResMut<BoardOrientation>,
+) {
+    let color = setup.color.unwrap_or_else(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.subsec_nanos());
+        if nanos.is_multiple_of(2) {
+            PieceColor::White
+        } else {
+            PieceColor::Black
+        }
+    });
+
+    (players.white, players.black) = match color {
+        _ if setup.observing => (PlayerKind::Remote, PlayerKind::Remote),
+        PieceColor::White => (PlayerKind::Human, setup.opponent),
+        PieceColor::Black => (setup.opponent, PlayerKind::Human),
+    };
+    orientation.bottom = color;
+
+    commands.trigger(NewGameEvent { swap_colors: false });
+}
@@ -0,0 +1,1401 @@
+// Games between two instances over TCP. One hosts with `--host [port]` and waits for the other to
+// `--join <address>`. The host starts the games from its setup screen, and the joiner follows.
+// Anyone else may `--watch <address>` the host's games.
+//
+// Both sides send lines of text, the first being `hello <version>`, or `hello <version> watch` from
+// a spectator. Versions that differ part ways.
+//
+//   start <color> <time control or -> <fen>   a new game, in which the joiner plays <color>
+//   move <uci>                               a move by the sender
+//   resign                                   the sender gives up
+//   draw offer                               the sender offers a draw, until their opponent moves
+//   draw accept                              the sender takes the draw offered to them
+//   rematch                                  the same game again with colors swapped
+//   cancel                                   the sender calls off a game not yet played, such as
+//                                            a rematch they did not want
+//   flag <color>                             <color> ran out of time
+//   clock <ply> <white ms> <black ms>        the clocks after <ply> moves, from a game server
+//   bye                                      the sender leaves
+//
+// Spectators only listen. They get `observe <time control or -> <fen> <uci moves>` with the game so
+// far, then its moves and clocks, and the `resign <color>`, `draw accept` or `flag <color>` that
+// ends it if the board cannot tell.
+//
+// Each side calls its own flag fall. A game server (see `server.rs`) plays the host's part for both
+// of its players, and its word on the clocks is final. The joiner says hello again whenever it
+// leaves a game, which asks a server for the next one.
+//
+// Incoming moves are checked like moves from the mouse and played through `MoveRequestedEvent`.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    iter::Peekable,
+    net::{TcpListener, TcpStream},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    chess::{Move, is_legal_move, is_promotion, parse_fen, parse_uci_move, to_fen, to_uci_move},
+    clock::{Clocks, tick_clock_system, timeout_result},
+    components::{DrawButton, NetStatusText, Piece, PieceColor, PieceKind, ResignButton, Square},
+    events::{MoveRequestedEvent, NewGameEvent},
+    menu::start_game,
+    pgn::PgnGame,
+    resources::{
+        GameEndReason, GameResult, GameSetup, GameState, MoveHistory, PlayerKind, Players,
+    },
+    states::{AppState, InGame},
+};
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const DEFAULT_PORT: u16 = 7878;
+// How long a write may wait for the other side to take it before the connection is given up.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct NetPlugin;
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetConfig>()
+            .add_systems(Startup, start_connection)
+            .add_systems(OnEnter(InGame), send_start.after(start_game))
+            .add_systems(OnExit(InGame), leave_game)
+            .add_observer(on_new_game)
+            .add_systems(
+                Update,
+                (
+                    read_network_system,
+                    begin_game_system
+                        .after(read_network_system)
+                        .run_if(in_state(AppState::MainMenu)),
+                    update_net_status_system.after(read_network_system),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    send_moves_system.after(read_network_system),
+                    abandon_system.after(read_network_system),
+                    send_result_system,
+                    draw_button_system,
+                    update_draw_button_system.after(draw_button_system),
+                    update_resign_button_system,
+                    update_spectators_system
+                        .after(send_moves_system)
+                        .after(tick_clock_system),
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NetRole {
+    // Listens on this port.
+    Host(u16),
+    // Connects to this address.
+    Join(String),
+    // Connects to this address to watch its games.
+    Watch(String),
+}
+
+#[derive(Resource, Clone, Default, Debug)]
+pub struct NetConfig {
+    pub role: Option<NetRole>,
+}
+
+impl NetConfig {
+    /// Reads `--host [port]`, `--join <address>` and `--watch <address>` from the command line.
+    /// The address may leave out the port, which is then the default one.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut config = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--host" => config.role = Some(NetRole::Host(optional_port(&mut args))),
+                "--join" => match args.next() {
+                    Some(address) => config.role = Some(NetRole::Join(with_port(address))),
+                    None => warn!("--join needs an address"),
+                },
+                "--watch" => match args.next() {
+                    Some(address) => config.role = Some(NetRole::Watch(with_port(address))),
+                    None => warn!("--watch needs an address"),
+                },
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+fn with_port(address: String) -> String {
+    if address.contains(':') {
+        address
+    } else {
+        format!("{address}:{DEFAULT_PORT}")
+    }
+}
+
+/// The port after `--host` or `--server`, if one is given.
+pub fn optional_port(args: &mut Peekable<impl Iterator<Item = String>>) -> u16 {
+    match args.peek().and_then(|port| port.parse().ok()) {
+        Some(port) => {
+            args.next();
+            port
+        }
+        None => DEFAULT_PORT,
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NetMessage {
+    Hello {
+        version: u32,
+        // Sent by a spectator.
+        watch: bool,
+    },
+    Start {
+        // The joiner's side.
+        color: PieceColor,
+        time_control: Option<String>,
+        fen: String,
+    },
+    Observe {
+        time_control: Option<String>,
+        fen: String,
+        moves: Vec<Move>,
+    },
+    Move(Move),
+    Resign,
+    // Tells a spectator that this side resigned.
+    Resigned(PieceColor),
+    DrawOffer,
+    DrawAccept,
+    Rematch,
+    Cancel,
+    Flag(PieceColor),
+    Clock {
+        ply: usize,
+        white: Duration,
+        black: Duration,
+    },
+    Bye,
+}
+
+impl NetMessage {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let message = match words.next()? {
+            "hello" => Self::Hello {
+                version: words.next()?.parse().ok()?,
+                watch: words.next() == Some("watch"),
+            },
+            "start" => {
+                let color = parse_color(words.next()?)?;
+                let time_control = match words.next()? {
+                    "-" => None,
+                    spec => Some(spec.to_string()),
+                };
+                let fen = words.collect::<Vec<_>>().join(" ");
+                if fen.is_empty() {
+                    return None;
+                }
+                Self::Start {
+                    color,
+                    time_control,
+                    fen,
+                }
+            }
+            "observe" => {
+                let time_control = match words.next()? {
+                    "-" => None,
+                    spec => Some(spec.to_string()),
+                };
+                // The FEN has six fields, and the moves come after it.
+                let fen = words.by_ref().take(6).collect::<Vec<_>>().join(" ");
+                if fen.is_empty() {
+                    return None;
+                }
+                Self::Observe {
+                    time_control,
+                    fen,
+                    moves: words.map(parse_uci_move).collect::<Option<_>>()?,
+                }
+            }
+            "move" => Self::Move(parse_uci_move(words.next()?)?),
+            "resign" => match words.next() {
+                Some(color) => Self::Resigned(parse_color(color)?),
+                None => Self::Resign,
+            },
+            "draw" => match words.next()? {
+                "offer" => Self::DrawOffer,
+                "accept" => Self::DrawAccept,
+                _ => return None,
+            },
+            "rematch" => Self::Rematch,
+            "cancel" => Self::Cancel,
+            "flag" => Self::Flag(parse_color(words.next()?)?),
+            "clock" => {
+                let mut number = || words.next()?.parse::<u64>().ok();
+                Self::Clock {
+                    ply: number()? as usize,
+                    white: Duration::from_millis(number()?),
+                    black: Duration::from_millis(number()?),
+                }
+            }
+            "bye" => Self::Bye,
+            _ => return None,
+        };
+        Some(message)
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Hello {
+                version,
+                watch: false,
+            } => format!("hello {version}"),
+            Self::Hello {
+                version,
+                watch: true,
+            } => format!("hello {version} watch"),
+            Self::Start {
+                color,
+                time_control,
+                fen,
+            } => format!(
+                "start {} {} {fen}",
+                color_name(*color),
+                time_control.as_deref().unwrap_or("-")
+            ),
+            Self::Observe {
+                time_control,
+                fen,
+                moves,
+            } => {
+                let moves: String = moves
+                    .iter()
+                    .map(|(start, end, promotion)| {
+                        format!(" {}", to_uci_move(*start, *end, *promotion))
+                    })
+                    .collect();
+                format!(
+                    "observe {} {fen}{moves}",
+                    time_control.as_deref().unwrap_or("-")
+                )
+            }
+            Self::Move((start, end, promotion)) => {
+                format!("move {}", to_uci_move(*start, *end, *promotion))
+            }
+            Self::Resign => "resign".to_string(),
+            Self::Resigned(color) => format!("resign {}", color_name(*color)),
+            Self::DrawOffer => "draw offer".to_string(),
+            Self::DrawAccept => "draw accept".to_string(),
+            Self::Rematch => "rematch".to_string(),
+            Self::Cancel => "cancel".to_string(),
+            Self::Flag(color) => format!("flag {}", color_name(*color)),
+            Self::Clock { ply, white, black } => format!(
+                "clock {ply} {} {}",
+                white.as_millis(),
+                black.as_millis()
+            ),
+            Self::Bye => "bye".to_string(),
+        }
+    }
+}
+
+fn parse_color(word: &str) -> Option<PieceColor> {
+    match word {
+        "white" => Some(PieceColor::White),
+        "black" => Some(PieceColor::Black),
+        _ => None,
+    }
+}
+
+fn color_name(color: PieceColor) -> &'static str {
+    match color {
+        PieceColor::White => "white",
+        PieceColor::Black => "black",
+    }
+}
+
+// What the network threads pass on, for each connection by number.
+enum NetEvent {
+    // The stream to write to.
+    Connected(usize, TcpStream),
+    Message(usize, NetMessage),
+    Closed(usize, String),
+    // Listening or connecting failed.
+    Failed(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NetStatus {
+    Connecting,
+    // The handshake is done.
+    Connected(String),
+    Closed(String),
+}
+
+// Someone watching the host's games.
+struct Spectator {
+    stream: TcpStream,
+    id: usize,
+    // Whether they were sent the current game, how many of its moves and how it ended.
+    observing: bool,
+    moves_sent: usize,
+    ending_sent: bool,
+}
+
+#[derive(Resource)]
+pub struct NetConnection {
+    pub role: NetRole,
+    pub status: NetStatus,
+    events: Mutex<Receiver<NetEvent>>,
+    // The connection to the other player, or to the host being watched, once it said hello.
+    stream: Option<TcpStream>,
+    peer: Option<usize>,
+    // Connections that have not said hello yet.
+    greeting: Vec<(usize, TcpStream)>,
+    spectators: Vec<Spectator>,
+    // Draw offers made in this game, each good until the other side moves.
+    draw_offered: bool,
+    draw_received: bool,
+    // The next rematch was asked for by the other side, so it is not sent back.
+    rematch_received: bool,
+    // A rematch was asked for here, so one asked for by the other side at the same time is the
+    // same game.
+    rematch_sent: bool,
+    // The joiner was in a game when the host started a new one, and goes through the main menu.
+    pending_start: bool,
+    // Moves of the game so far that were sent or received.
+    moves_seen: usize,
+    // Events held back while a new game is being set up.
+    backlog: Vec<NetEvent>,
+}
+
+impl NetConnection {
+    pub fn is_connected(&self) -> bool {
+        matches!(self.status, NetStatus::Connected(_))
+    }
+
+    /// Whether a game over the network can be started from this side.
+    pub fn can_host_game(&self) -> bool {
+        matches!(self.role, NetRole::Host(_)) && self.is_connected()
+    }
+
+    pub fn send(&mut self, message: &NetMessage) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        if let Err(err) = write_message(stream, message) {
+            warn!("Could not send {:?}: {err}", message.to_line());
+            self.lose_player(err.to_string());
+        }
+    }
+
+    fn close(&mut self, reason: String) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        self.peer = None;
+        self.status = NetStatus::Closed(reason);
+    }
+
+    // The other player is gone. A host waits for the next one, and for anyone else the connection
+    // is over.
+    fn lose_player(&mut self, reason: String) {
+        let hosting = matches!(self.role, NetRole::Host(_));
+        if hosting {
+            warn!("The other player is gone: {reason}");
+        }
+        self.close(reason);
+        if hosting {
+            self.status = NetStatus::Connecting;
+        }
+    }
+
+    // Answers a hello. The first player to say it is the other side of the games, and after them
+    // the host only takes spectators.
+    fn greet(&mut self, id: usize, version: u32, watch: bool) {
+        let Some(index) = self
+            .greeting
+            .iter()
+            .position(|(greeting, _)| *greeting == id)
+        else {
+            return;
+        };
+        let (_, mut stream) = self.greeting.remove(index);
+        let peer = peer_name(&stream);
+        let hosting = matches!(self.role, NetRole::Host(_));
+
+        if version != PROTOCOL_VERSION {
+            error!("{peer} speaks protocol version {version}, not {PROTOCOL_VERSION}");
+            let _ = write_message(&mut stream, &NetMessage::Bye);
+            if !hosting {
+                self.close(format!("protocol version {version} is not supported"));
+            }
+        } else if hosting && watch {
+            info!("{peer} is watching");
+            self.spectators.push(Spectator {
+                stream,
+                id,
+                observing: false,
+                moves_sent: 0,
+                ending_sent: false,
+            });
+        } else if self.peer.is_some() {
+            warn!("{peer} wanted to play, but there already is a player");
+            let _ = write_message(&mut stream, &NetMessage::Bye);
+        } else {
+            info!("Connected to {peer}");
+            self.stream = Some(stream);
+            self.peer = Some(id);
+            self.status = NetStatus::Connected(peer);
+        }
+    }
+
+    // A spectator leaving is no matter, but the other player leaving ends the connection, or
+    // makes a host wait for someone else. Apart from a host, there only is the one.
+    fn disconnected(&mut self, id: usize, reason: String) {
+        self.greeting.retain(|(greeting, _)| *greeting != id);
+        self.spectators.retain(|spectator| spectator.id != id);
+        if !matches!(self.role, NetRole::Host(_)) {
+            warn!("Network connection closed: {reason}");
+            self.close(reason);
+        } else if self.peer == Some(id) {
+            self.lose_player(reason);
+        }
+    }
+}
+
+impl Drop for NetConnection {
+    fn drop(&mut self) {
+        self.send(&NetMessage::Bye);
+        for spectator in self.spectators.iter_mut() {
+            let _ = write_message(&mut spectator.stream, &NetMessage::Bye);
+        }
+    }
+}
+
+fn write_message(stream: &mut TcpStream, message: &NetMessage) -> std::io::Result<()> {
+    writeln!(stream, "{}", message.to_line())?;
+    stream.flush()
+}
+
+fn peer_name(stream: &TcpStream) -> String {
+    stream
+        .peer_addr()
+        .map_or("the other player".to_string(), |peer| peer.to_string())
+}
+
+// Opens the connection on a background thread. A host keeps taking connections, for its
+// spectators, and each one is read on a thread of its own.
+fn start_connection(mut commands: Commands, config: Res<NetConfig>) {
+    let Some(role) = config.role.clone() else {
+        return;
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let thread_role = role.clone();
+    let spawned = thread::Builder::new()
+        .name("net-connect".into())
+        .spawn(move || match &thread_role {
+            NetRole::Host(port) => {
+                let listener = match TcpListener::bind(("0.0.0.0", *port)) {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        let _ = sender.send(NetEvent::Failed(err.to_string()));
+                        return;
+                    }
+                };
+                for (id, stream) in (0..).zip(listener.incoming()) {
+                    match stream {
+                        Ok(stream) => spawn_reader(id, stream, sender.clone()),
+                        Err(err) => warn!("Could not accept a connection: {err}"),
+                    }
+                }
+            }
+            NetRole::Join(address) | NetRole::Watch(address) => {
+                match TcpStream::connect(address) {
+                    Ok(stream) => read_connection(0, stream, sender),
+                    Err(err) => {
+                        let _ = sender.send(NetEvent::Failed(err.to_string()));
+                    }
+                }
+            }
+        });
+    if let Err(err) = spawned {
+        error!("Could not start the network thread: {err}");
+        return;
+    }
+
+    match &role {
+        NetRole::Host(port) => info!("Waiting for a player on port {port}"),
+        NetRole::Join(address) => info!("Connecting to {address}"),
+        NetRole::Watch(address) => info!("Connecting to {address} to watch"),
+    }
+    commands.insert_resource(NetConnection {
+        role,
+        status: NetStatus::Connecting,
+        events: Mutex::new(receiver),
+        stream: None,
+        peer: None,
+        greeting: Vec::new(),
+        spectators: Vec::new(),
+        draw_offered: false,
+        draw_received: false,
+        rematch_received: false,
+        rematch_sent: false,
+        pending_start: false,
+        moves_seen: 0,
+        backlog: Vec::new(),
+    });
+}
+
+fn spawn_reader(id: usize, stream: TcpStream, sender: Sender<NetEvent>) {
+    let spawned = thread::Builder::new()
+        .name(format!("net-reader-{id}"))
+        .spawn(move || read_connection(id, stream, sender));
+    if let Err(err) = spawned {
+        error!("Could not start a network thread: {err}");
+    }
+}
+
+// Passes on the stream to write to, then every message read from it. Writes to the stream wait at
+// most `WRITE_TIMEOUT`.
+fn read_connection(id: usize, stream: TcpStream, sender: Sender<NetEvent>) {
+    let reader = match stream
+        .set_nodelay(true)
+        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+        .and_then(|_| stream.try_clone())
+    {
+        Ok(reader) => {
+            if sender.send(NetEvent::Connected(id, stream)).is_err() {
+                return;
+            }
+            reader
+        }
+        Err(err) => {
+            let _ = sender.send(NetEvent::Closed(id, err.to_string()));
+            return;
+        }
+    };
+
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else { break };
+        match NetMessage::parse(&line) {
+            Some(message) => {
+                if sender.send(NetEvent::Message(id, message)).is_err() {
+                    return;
+                }
+            }
+            None => warn!("Ignoring unknown message {line:?}"),
+        }
+    }
+    let _ = sender.send(NetEvent::Closed(
+        id,
+        "the connection was closed".to_string(),
+    ));
+}
+
+fn opponent(color: PieceColor) -> PieceColor {
+    match color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    }
+}
+
+// Whether a move from the other side may be played: it is their turn, and the move is legal with
+// a promotion exactly when a pawn reaches the last rank.
+fn is_valid_remote_move(
+    (start, end, promotion): Move,
+    board: &[(Piece, Square)],
+    game_state: &GameState,
+    players: &Players,
+) -> bool {
+    if game_state.result.is_some() || players.get(game_state.turn) != PlayerKind::Remote {
+        return false;
+    }
+    board.iter().any(|(piece, square)| {
+        (square.x, square.y) == start
+            && piece.color == game_state.turn
+            && is_legal_move(piece, start, end, board, game_state.en_passant_target)
+            && is_promotion(piece, end) == promotion.is_some()
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_network_system(
+    mut commands: Commands,
+    connection: Option<ResMut<NetConnection>>,
+    piece_query: Query<(&Piece, &Square)>,
+    mut game_state: ResMut<GameState>,
+    mut setup: ResMut<GameSetup>,
+    players: Res<Players>,
+    mut clocks: Option<ResMut<Clocks>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    let mut events = std::mem::take(&mut connection.backlog);
+    if let Ok(receiver) = connection.events.lock() {
+        events.extend(receiver.try_iter());
+    }
+    let in_game = matches!(state.get(), AppState::Playing | AppState::GameOver);
+
+    let mut events = events.into_iter();
+    let mut waiting = false;
+    while let Some(event) = events.next() {
+        // What comes after a new game or a move waits until it is on the board, so that the next
+        // move is checked against the position it was played in.
+        if waiting || connection.pending_start {
+            connection.backlog.push(event);
+            connection.backlog.extend(events);
+            break;
+        }
+        let message = match event {
+            NetEvent::Connected(id, mut stream) => {
+                let hello = NetMessage::Hello {
+                    version: PROTOCOL_VERSION,
+                    watch: matches!(connection.role, NetRole::Watch(_)),
+                };
+                match write_message(&mut stream, &hello) {
+                    Ok(()) => connection.greeting.push((id, stream)),
+                    Err(err) => connection.disconnected(id, err.to_string()),
+                }
+                continue;
+            }
+            NetEvent::Closed(id, reason) => {
+                connection.disconnected(id, reason);
+                continue;
+            }
+            NetEvent::Failed(reason) => {
+                error!("Could not connect: {reason}");
+                connection.close(reason);
+                continue;
+            }
+            NetEvent::Message(id, NetMessage::Hello { version, watch }) => {
+                connection.greet(id, version, watch);
+                continue;
+            }
+            NetEvent::Message(id, message) if connection.peer == Some(id) => message,
+            // Spectators only listen.
+            NetEvent::Message(..) => continue,
+        };
+
+        match message {
+            NetMessage::Hello { .. } => {}
+            // The host starts the games.
+            NetMessage::Start {
+                color,
+                time_control,
+                fen,
+            } if matches!(connection.role, NetRole::Join(_)) => {
+                setup.opponent = PlayerKind::Remote;
+                setup.color = Some(color);
+                setup.time_control = time_control;
+                setup.start_fen = Some(fen);
+                begin_game(&mut connection, in_game, &mut next_state);
+                waiting = true;
+            }
+            NetMessage::Start { .. } => warn!("Only the host starts games"),
+            NetMessage::Observe {
+                time_control,
+                fen,
+                moves,
+            } if matches!(connection.role, NetRole::Watch(_)) => {
+                let Some(start) = parse_fen(&fen) else {
+                    warn!("Cannot watch a game from {fen:?}");
+                    continue;
+                };
+                let mut position = start.clone();
+                let illegal = moves.iter().find(|&&(start, end, promotion)| {
+                    let legal = position.is_legal((start, end, promotion));
+                    if legal {
+                        position = position.play(start, end, promotion);
+                    }
+                    !legal
+                });
+                if let Some(&(start, end, promotion)) = illegal {
+                    warn!(
+                        "Cannot watch a game with the illegal move {}",
+                        to_uci_move(start, end, promotion)
+                    );
+                    continue;
+                }
+
+                setup.opponent = PlayerKind::Remote;
+                setup.observing = true;
+                setup.color = Some(PieceColor::White);
+                setup.time_control = time_control;
+                setup.start_fen = Some(fen);
+                setup.loaded_game = Some(PgnGame {
+                    start,
+                    moves,
+                    annotations: Vec::new(),
+                });
+                begin_game(&mut connection, in_game, &mut next_state);
+                waiting = true;
+            }
+            NetMessage::Observe { .. } => warn!("Only spectators watch games"),
+            NetMessage::Move(mv) => {
+                let board: Vec<(Piece, Square)> =
+                    piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+                if !is_valid_remote_move(mv, &board, &game_state, &players) {
+                    error!(
+                        "The other player sent an illegal move {}",
+                        to_uci_move(mv.0, mv.1, mv.2)
+                    );
+                    connection.send(&NetMessage::Bye);
+                    connection.lose_player("an illegal move was received".to_string());
+                    continue;
+                }
+                connection.moves_seen += 1;
+                let (start, end, promotion) = mv;
+                commands.trigger(MoveRequestedEvent {
+                    start,
+                    end,
+                    promotion,
+                });
+                waiting = true;
+            }
+            NetMessage::Resign => {
+                if let Some(color) = remote_color(&players)
+                    && game_state.result.is_none()
+                {
+                    game_state.result = Some(GameResult {
+                        winner: Some(opponent(color)),
+                        reason: GameEndReason::Resignation,
+                    });
+                }
+            }
+            NetMessage::Resigned(color) => {
+                if players.is_observing() && game_state.result.is_none() {
+                    game_state.result = Some(GameResult {
+                        winner: Some(opponent(color)),
+                        reason: GameEndReason::Resignation,
+                    });
+                }
+            }
+            NetMessage::DrawOffer => connection.draw_received = true,
+            NetMessage::DrawAccept => {
+                if (connection.draw_offered || players.is_observing())
+                    && game_state.result.is_none()
+                {
+                    game_state.result = Some(GameResult {
+                        winner: None,
+                        reason: GameEndReason::Agreement,
+                    });
+                }
+            }
+            // Both sides may ask at once, so a request only counts once the game is over. One
+            // that comes too late is turned down.
+            NetMessage::Rematch => {
+                if *state.get() == AppState::GameOver && players.has_remote() {
+                    connection.rematch_received = true;
+                    commands.trigger(NewGameEvent { swap_colors: true });
+                    next_state.set(AppState::Playing);
+                } else if connection.rematch_sent {
+                    connection.rematch_sent = false;
+                } else {
+                    connection.send(&NetMessage::Cancel);
+                }
+            }
+            NetMessage::Cancel => {
+                if in_game && players.has_remote() && game_state.result.is_none() {
+                    warn!("The other player called the game off");
+                    next_state.set(AppState::MainMenu);
+                }
+            }
+            NetMessage::Flag(color) => {
+                if players.has_remote() && game_state.result.is_none() {
+                    let board: Vec<(Piece, Square)> =
+                        piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+                    game_state.result = Some(timeout_result(color, &board));
+                }
+            }
+            NetMessage::Clock { ply, white, black } => {
+                if let Some(clocks) = clocks.as_mut()
+                    && in_game
+                {
+                    clocks.sync(ply, white, black);
+                }
+            }
+            NetMessage::Bye => connection.lose_player("the other player left".to_string()),
+        }
+    }
+}
+
+// A game against the other player can't go on without them, so it is won by the side that stayed.
+// Spectators only lose sight of the game.
+fn abandon_system(
+    connection: Option<Res<NetConnection>>,
+    players: Res<Players>,
+    mut game_state: ResMut<GameState>,
+) {
+    let Some(connection) = connection else {
+        return;
+    };
+    if connection.is_connected()
+        || game_state.result.is_some()
+        || matches!(connection.role, NetRole::Watch(_))
+    {
+        return;
+    }
+    if let Some(color) = remote_color(&players) {
+        game_state.result = Some(GameResult {
+            winner: Some(opponent(color)),
+            reason: GameEndReason::Abandonment,
+        });
+    }
+}
+
+// Starts the game the other side sent, going through the main menu out of a game in progress.
+fn begin_game(connection: &mut NetConnection, in_game: bool, next_state: &mut NextState<AppState>) {
+    if in_game {
+        next_state.set(AppState::MainMenu);
+        connection.pending_start = true;
+    } else {
+        next_state.set(AppState::Playing);
+    }
+}
+
+fn remote_color(players: &Players) -> Option<PieceColor> {
+    [PieceColor::White, PieceColor::Black]
+        .into_iter()
+        .find(|color| players.get(*color) == PlayerKind::Remote)
+}
+
+// The joiner or spectator was pulled out of its game to start the new one.
+fn begin_game_system(
+    connection: Option<ResMut<NetConnection>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if let Some(mut connection) = connection
+        && connection.pending_start
+    {
+        connection.pending_start = false;
+        next_state.set(AppState::Playing);
+    }
+}
+
+// The host tells the joiner about each game started from the setup screen.
+fn send_start(
+    connection: Option<ResMut<NetConnection>>,
+    setup: Res<GameSetup>,
+    players: Res<Players>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    if !connection.can_host_game() {
+        return;
+    }
+    let Some(color) = remote_color(&players) else {
+        return;
+    };
+
+    let start = setup.start_position();
+    let fen = to_fen(
+        &start.board,
+        start.turn,
+        start.en_passant,
+        start.halfmove_clock,
+        start.fullmove_number,
+    );
+    connection.send(&NetMessage::Start {
+        color,
+        time_control: setup.time_control.clone(),
+        fen,
+    });
+}
+
+// Every new game starts without offers, and spectators get to see it. A rematch asked for here is
+// passed on, unless this side only watches.
+fn on_new_game(event: On<NewGameEvent>, connection: Option<ResMut<NetConnection>>) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    connection.draw_offered = false;
+    connection.draw_received = false;
+    connection.moves_seen = 0;
+    connection.rematch_sent = event.swap_colors
+        && !connection.rematch_received
+        && !matches!(connection.role, NetRole::Watch(_));
+    if connection.rematch_sent {
+        connection.send(&NetMessage::Rematch);
+    }
+    connection.rematch_received = false;
+    for spectator in connection.spectators.iter_mut() {
+        spectator.observing = false;
+    }
+}
+
+// The joiner is done with its game, which a game server takes as asking for the next one.
+fn leave_game(connection: Option<ResMut<NetConnection>>) {
+    if let Some(mut connection) = connection
+        && matches!(connection.role, NetRole::Join(_))
+        && connection.is_connected()
+        && !connection.pending_start
+    {
+        connection.send(&NetMessage::Hello {
+            version: PROTOCOL_VERSION,
+            watch: false,
+        });
+    }
+}
+
+// Sends the moves played here. A move by either side ends the draw offer made to that side.
+fn send_moves_system(
+    connection: Option<ResMut<NetConnection>>,
+    history: Res<MoveHistory>,
+    players: Res<Players>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    if !history.is_changed() || !players.has_remote() {
+        return;
+    }
+
+    // The received moves are counted when they arrive, so only this side's moves are left.
+    while connection.moves_seen < history.moves.len() {
+        let ply = connection.moves_seen;
+        let record = &history.moves[ply];
+        let mover = opponent(record.position.turn);
+        connection.moves_seen += 1;
+        if players.get(mover) == PlayerKind::Remote {
+            connection.draw_offered = false;
+            continue;
+        }
+        connection.draw_received = false;
+        connection.send(&NetMessage::Move(recorded_move(&history, ply)));
+    }
+}
+
+// The move made at `ply`. A promotion shows as another piece on the pawn's last square.
+fn recorded_move(history: &MoveHistory, ply: usize) -> Move {
+    let record = &history.moves[ply];
+    let promotion = record
+        .position
+        .board
+        .iter()
+        .find(|(_, square)| (square.x, square.y) == record.end)
+        .map(|(piece, _)| piece.kind)
+        .filter(|kind| {
+            *kind != PieceKind::Pawn
+                && history.position(ply).is_some_and(|before| {
+                    before.board.iter().any(|(piece, square)| {
+                        (square.x, square.y) == record.start && piece.kind == PieceKind::Pawn
+                    })
+                })
+        });
+    (record.start, record.end, promotion)
+}
+
+// Brings the host's spectators up to date: the game so far when they start watching it, then each
+// move with the clocks after it, and how the game ended if their board cannot tell.
+fn update_spectators_system(
+    connection: Option<ResMut<NetConnection>>,
+    history: Res<MoveHistory>,
+    game_state: Res<GameState>,
+    setup: Res<GameSetup>,
+    clocks: Option<Res<Clocks>>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    if !history.is_changed()
+        && !game_state.is_changed()
+        && connection
+            .spectators
+            .iter()
+            .all(|spectator| spectator.observing)
+    {
+        return;
+    }
+
+    let moves: Vec<Move> = (0..history.moves.len())
+        .map(|ply| recorded_move(&history, ply))
+        .collect();
+    let fen = history
+        .start
+        .clone()
+        .unwrap_or_else(|| setup.start_position())
+        .fen();
+    let clock = clocks.map(|clocks| NetMessage::Clock {
+        ply: moves.len(),
+        white: clocks.white.remaining,
+        black: clocks.black.remaining,
+    });
+    let ending = game_state.result.and_then(|result| match result.reason {
+        GameEndReason::Resignation => result
+            .winner
+            .map(|winner| NetMessage::Resigned(opponent(winner))),
+        GameEndReason::Agreement => Some(NetMessage::DrawAccept),
+        // The clock that ran out is the one of the side to move.
+        GameEndReason::Timeout | GameEndReason::TimeoutVsInsufficientMaterial => {
+            Some(NetMessage::Flag(game_state.turn))
+        }
+        _ => None,
+    });
+
+    connection.spectators.retain_mut(|spectator| {
+        let mut messages = Vec::new();
+        if !spectator.observing {
+            messages.push(NetMessage::Observe {
+                time_control: setup.time_control.clone(),
+                fen: fen.clone(),
+                moves: moves.clone(),
+            });
+            messages.extend(clock.clone());
+            spectator.observing = true;
+            spectator.ending_sent = false;
+        } else if spectator.moves_sent < moves.len() {
+            messages.extend(
+                moves[spectator.moves_sent..]
+                    .iter()
+                    .map(|mv| NetMessage::Move(*mv)),
+            );
+            messages.extend(clock.clone());
+        }
+        spectator.moves_sent = moves.len();
+        if !spectator.ending_sent
+            && let Some(ending) = &ending
+        {
+            messages.push(ending.clone());
+            spectator.ending_sent = true;
+        }
+
+        // Spectators that cannot be reached, or don't keep up, are let go.
+        let reached = messages
+            .iter()
+            .all(|message| write_message(&mut spectator.stream, message).is_ok());
+        if !reached {
+            let _ = spectator.stream.shutdown(std::net::Shutdown::Both);
+        }
+        reached
+    });
+}
+
+// Tells the other side when the player here resigned or ran out of time.
+fn send_result_system(
+    connection: Option<ResMut<NetConnection>>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    mut was_over: Local<bool>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    let over = game_state.result.is_some();
+    let just_ended = over && !*was_over;
+    *was_over = over;
+    let Some(result) = game_state
+        .result
+        .filter(|_| just_ended && players.has_remote())
+    else {
+        return;
+    };
+
+    match result.reason {
+        GameEndReason::Resignation
+            if result
+                .winner
+                .is_some_and(|winner| players.get(opponent(winner)) != PlayerKind::Remote) =>
+        {
+            connection.send(&NetMessage::Resign);
+        }
+        // The clock that ran out is the one of the side to move.
+        GameEndReason::Timeout | GameEndReason::TimeoutVsInsufficientMaterial
+            if players.get(game_state.turn) != PlayerKind::Remote =>
+        {
+            connection.send(&NetMessage::Flag(game_state.turn));
+        }
+        _ => {}
+    }
+}
+
+fn draw_button_system(
+    button_query: Query<&Interaction, (Changed<Interaction>, With<DrawButton>)>,
+    connection: Option<ResMut<NetConnection>>,
+    players: Res<Players>,
+    mut game_state: ResMut<GameState>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+    for interaction in button_query.iter() {
+        if *interaction != Interaction::Pressed
+            || game_state.result.is_some()
+            || !players.has_remote()
+            || players.is_observing()
+        {
+            continue;
+        }
+
+        if connection.draw_received {
+            connection.send(&NetMessage::DrawAccept);
+            game_state.result = Some(GameResult {
+                winner: None,
+                reason: GameEndReason::Agreement,
+            });
+        } else if !connection.draw_offered {
+            connection.send(&NetMessage::DrawOffer);
+            connection.draw_offered = true;
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_draw_button_system(
+    connection: Option<Res<NetConnection>>,
+    players: Res<Players>,
+    mut button_query: Query<(&mut Visibility, &Children), With<DrawButton>>,
+    mut text_query: Query<&mut Text>,
+) {
+    let label = match &connection {
+        Some(connection) if connection.draw_received => "Accept draw",
+        Some(connection) if connection.draw_offered => "Draw offered",
+        _ => "Offer draw",
+    };
+    let visibility = if connection.is_some() && players.has_remote() && !players.is_observing() {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+
+    for (mut button_visibility, children) in button_query.iter_mut() {
+        button_visibility.set_if_neq(visibility);
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child)
+                && text.0 != label
+            {
+                text.0 = label.to_string();
+            }
+        }
+    }
+}
+
+// Spectators have nothing to resign.
+fn update_resign_button_system(
+    players: Res<Players>,
+    mut button_query: Query<&mut Visibility, With<ResignButton>>,
+) {
+    let visibility = if players.is_observing() {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    for mut button_visibility in button_query.iter_mut() {
+        button_visibility.set_if_neq(visibility);
+    }
+}
+
+fn update_net_status_system(
+    connection: Option<Res<NetConnection>>,
+    mut text_query: Query<&mut Text, With<NetStatusText>>,
+) {
+    let status = match connection.as_deref() {
+        None => String::new(),
+        Some(connection) => match (&connection.status, &connection.role) {
+            (NetStatus::Connecting, NetRole::Host(port)) => {
+                format!("Hosting on port {port}, waiting for a player")
+            }
+            (NetStatus::Connecting, NetRole::Join(address)) => format!("Connecting to {address}"),
+            (NetStatus::Connecting, NetRole::Watch(address)) => {
+                format!("Connecting to {address} to watch")
+            }
+            (NetStatus::Connected(peer), NetRole::Host(_)) => match connection.spectators.len() {
+                0 => format!("{peer} joined"),
+                watching => format!("{peer} joined, {watching} watching"),
+            },
+            (NetStatus::Connected(peer), NetRole::Join(_)) => {
+                format!("Connected to {peer}, waiting for a game")
+            }
+            (NetStatus::Connected(peer), NetRole::Watch(_)) => {
+                format!("Observing the games at {peer}")
+            }
+            (NetStatus::Closed(reason), _) => format!("Disconnected: {reason}"),
+        },
+    };
+
+    for mut text in text_query.iter_mut() {
+        if text.0 != status {
+            text.0 = status.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chess::START_FEN, settings::UserSettings, uci::UciConfig};
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let e4 = parse_uci_move("e2e4").unwrap();
+        let promotion = parse_uci_move("a7a8q").unwrap();
+        let messages = [
+            NetMessage::Hello {
+                version: PROTOCOL_VERSION,
+                watch: false,
+            },
+            NetMessage::Hello {
+                version: PROTOCOL_VERSION,
+                watch: true,
+            },
+            NetMessage::Start {
+                color: PieceColor::Black,
+                time_control: Some("40/90+30:30+30".to_string()),
+                fen: START_FEN.to_string(),
+            },
+            NetMessage::Start {
+                color: PieceColor::White,
+                time_control: None,
+                fen: "4k3/P7/8/8/8/8/8/4K3 w - - 0 1".to_string(),
+            },
+            NetMessage::Observe {
+                time_control: Some("5+3".to_string()),
+                fen: START_FEN.to_string(),
+                moves: vec![e4, promotion],
+            },
+            NetMessage::Observe {
+                time_control: None,
+                fen: START_FEN.to_string(),
+                moves: Vec::new(),
+            },
+            NetMessage::Move(e4),
+            NetMessage::Move(promotion),
+            NetMessage::Resign,
+            NetMessage::Resigned(PieceColor::White),
+            NetMessage::DrawOffer,
+            NetMessage::DrawAccept,
+            NetMessage::Rematch,
+            NetMessage::Cancel,
+            NetMessage::Flag(PieceColor::Black),
+            NetMessage::Clock {
+                ply: 12,
+                white: Duration::from_millis(61_250),
+                black: Duration::from_secs(300),
+            },
+            NetMessage::Bye,
+        ];
+        for message in messages {
+            assert_eq!(NetMessage::parse(&message.to_line()), Some(message));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_incomplete_messages() {
+        for line in [
+            "",
+            "hi",
+            "hello",
+            "move",
+            "move e9e4",
+            "flag red",
+            "clock 1 2",
+            "draw",
+        ] {
+            assert_eq!(NetMessage::parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn a_move_waits_for_the_one_before() {
+        let (sender, receiver) = mpsc::channel();
+        let mut app = App::new();
+        app.add_plugins(bevy::state::app::StatesPlugin)
+            .insert_state(AppState::Playing)
+            .insert_resource(NetConnection {
+                role: NetRole::Join("localhost".to_string()),
+                status: NetStatus::Connected("localhost".to_string()),
+                events: Mutex::new(receiver),
+                stream: None,
+                peer: Some(0),
+                greeting: Vec::new(),
+                spectators: Vec::new(),
+                draw_offered: false,
+                draw_received: false,
+                rematch_received: false,
+                rematch_sent: false,
+                pending_start: false,
+                moves_seen: 0,
+                backlog: Vec::new(),
+            })
+            .insert_resource(Players {
+                white: PlayerKind::Remote,
+                black: PlayerKind::Human,
+            })
+            .init_resource::<GameState>()
+            .insert_resource(GameSetup::from_args(
+                Vec::new(),
+                &UciConfig::default(),
+                &UserSettings::default(),
+            ))
+            .add_systems(Update, read_network_system);
+        for (piece, square) in parse_fen(START_FEN).unwrap().board {
+            app.world_mut().spawn((piece, square));
+        }
+
+        for uci in ["e2e4", "e7e5"] {
+            let message = NetMessage::Move(parse_uci_move(uci).unwrap());
+            sender.send(NetEvent::Message(0, message)).unwrap();
+        }
+        app.update();
+
+        // The second move can only be checked once the first is on the board.
+        let connection = app.world().resource::<NetConnection>();
+        assert_eq!(connection.moves_seen, 1);
+        assert_eq!(connection.backlog.len(), 1);
+    }
+
+    #[test]
+    fn losing_the_other_player_ends_the_game() {
+        let (_sender, receiver) = mpsc::channel();
+        let mut app = App::new();
+        app.insert_resource(NetConnection {
+            role: NetRole::Join("localhost".to_string()),
+            status: NetStatus::Closed("the other player left".to_string()),
+            events: Mutex::new(receiver),
+            stream: None,
+            peer: None,
+            greeting: Vec::new(),
+            spectators: Vec::new(),
+            draw_offered: false,
+            draw_received: false,
+            rematch_received: false,
+            rematch_sent: false,
+            pending_start: false,
+            moves_seen: 0,
+            backlog: Vec::new(),
+        })
+        .insert_resource(Players {
+            white: PlayerKind::Human,
+            black: PlayerKind::Remote,
+        })
+        .init_resource::<GameState>()
+        .add_systems(Update, abandon_system);
+        app.update();
+
+        let result = app.world().resource::<GameState>().result;
+        assert_eq!(
+            result,
+            Some(GameResult {
+                winner: Some(PieceColor::White),
+                reason: GameEndReason::Abandonment,
+            })
+        );
+    }
+}
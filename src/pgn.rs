@@ -0,0 +1,363 @@
+// Games in Portable Game Notation (PGN).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    chess::{Move, Position, START_FEN, parse_fen, parse_square, square_name},
+    components::PieceColor,
+    resources::{Annotations, MarkColor},
+};
+
+/// Writes a game from its tags, the position it started from and its moves in SAN. Games that did
+/// not start from the usual position get `SetUp` and `FEN` tags. `annotations` holds the arrows
+/// and marked squares of each position, starting with the one before the first move, and they are
+/// written as `[%cal]` and `[%csl]` comments.
+pub fn write_pgn(
+    tags: &[(&str, String)],
+    start: Option<&Position>,
+    moves: &[&str],
+    annotations: &[&Annotations],
+    result: &str,
+) -> String {
+    let mut pgn = String::new();
+    for (name, value) in tags {
+        pgn += &format!(
+            "[{name} \"{}\"]\n",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+    }
+    if let Some(start) = start
+        && start.fen() != START_FEN
+    {
+        pgn += &format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", start.fen());
+    }
+    pgn += "\n";
+
+    let (mut turn, mut number) = start.map_or((PieceColor::White, 1), |start| {
+        (start.turn, start.fullmove_number)
+    });
+    let mut tokens = Vec::new();
+    let comment = |ply: usize| {
+        annotations
+            .get(ply)
+            .and_then(|annotations| annotation_comment(annotations))
+    };
+    // After a comment, a move by Black gets its number again.
+    let mut commented = false;
+    if let Some(comment) = comment(0) {
+        tokens.push(comment);
+        commented = true;
+    }
+    for (i, san) in moves.iter().enumerate() {
+        match turn {
+            PieceColor::White => tokens.push(format!("{number}.")),
+            PieceColor::Black if i == 0 || commented => tokens.push(format!("{number}...")),
+            PieceColor::Black => {}
+        }
+        tokens.push(san.to_string());
+        commented = false;
+        if let Some(comment) = comment(i + 1) {
+            tokens.push(comment);
+            commented = true;
+        }
+
+        if turn == PieceColor::Black {
+            number += 1;
+        }
+        turn = match turn {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        };
+    }
+    tokens.push(result.to_string());
+
+    // Movetext lines are kept below 80 characters.
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+            pgn += &line;
+            pgn += "\n";
+            line.clear();
+        }
+        if !line.is_empty() {
+            line += " ";
+        }
+        line += &token;
+    }
+    pgn + &line + "\n"
+}
+
+// A comment like `{[%csl Gd4][%cal Ge2e4,Rd7d5]}`, or None when nothing is drawn.
+fn annotation_comment(annotations: &Annotations) -> Option<String> {
+    if annotations.is_empty() {
+        return None;
+    }
+
+    let mut comment = String::from("{");
+    if !annotations.squares.is_empty() {
+        let squares: Vec<String> = annotations
+            .squares
+            .iter()
+            .map(|(square, color)| format!("{}{}", color.letter(), square_name(*square)))
+            .collect();
+        comment += &format!("[%csl {}]", squares.join(","));
+    }
+    if !annotations.arrows.is_empty() {
+        let arrows: Vec<String> = annotations
+            .arrows
+            .iter()
+            .map(|(start, end, color)| {
+                format!(
+                    "{}{}{}",
+                    color.letter(),
+                    square_name(*start),
+                    square_name(*end)
+                )
+            })
+            .collect();
+        comment += &format!("[%cal {}]", arrows.join(","));
+    }
+    comment.push('}');
+    Some(comment)
+}
+
+// Picks the `[%cal]` and `[%csl]` commands out of a comment and ignores the rest of it.
+fn parse_annotations(comment: &str, annotations: &mut Annotations) {
+    let mut rest = comment;
+    while let Some(open) = rest.find("[%") {
+        let Some(close) = rest[open..].find(']') else {
+            break;
+        };
+        let command = &rest[open + 2..open + close];
+        rest = &rest[open + close + 1..];
+
+        let (name, arguments) = command.split_once(' ').unwrap_or((command, ""));
+        for argument in arguments.split(',').map(str::trim) {
+            let mut chars = argument.chars();
+            let Some(color) = chars.next().and_then(MarkColor::from_letter) else {
+                continue;
+            };
+            // Squares are plain ASCII; anything else would be cut inside a character below.
+            let squares = chars.as_str();
+            if !squares.is_ascii() {
+                continue;
+            }
+            match (name, squares.len()) {
+                ("csl", 2) => {
+                    if let Some(square) = parse_square(squares) {
+                        annotations.squares.push((square, color));
+                    }
+                }
+                ("cal", 4) => {
+                    if let (Some(start), Some(end)) =
+                        (parse_square(&squares[..2]), parse_square(&squares[2..]))
+                    {
+                        annotations.arrows.push((start, end, color));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// A game read from a PGN file.
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    pub start: Position,
+    pub moves: Vec<Move>,
+    // For each position, starting with the one before the first move.
+    pub annotations: Vec<Annotations>,
+}
+
+/// Reads the first game of a PGN file. Variations, NAGs and comments other than arrows and marked
+/// squares are skipped.
+pub fn read_pgn(text: &str) -> Result<PgnGame, String> {
+    let mut tags = Vec::new();
+    let mut moves = Vec::new();
+    let mut annotations = vec![Annotations::default()];
+    let mut position: Option<Position> = None;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let (name, value) = tag.split_once(' ').unwrap_or((&tag, ""));
+                let value = value
+                    .trim()
+                    .trim_matches('"')
+                    .replace("\\\"", "\"")
+                    .replace("\\\\", "\\");
+                tags.push((name.trim().to_string(), value));
+            }
+            '{' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                if let Some(last) = annotations.last_mut() {
+                    parse_annotations(&comment, last);
+                }
+            }
+            ';' => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+            }
+            '(' => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some('{') => chars.by_ref().take_while(|&c| c != '}').for_each(drop),
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+            }
+            '$' => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"{}()[];$".contains(*c))
+                {
+                    word.push(c);
+                }
+                if matches!(word.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+                    break;
+                }
+
+                // Move numbers, possibly run together with the move as in `1.e4`. Only digits
+                // followed by dots, so castling written as `0-0` keeps its zeros.
+                let numbered = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                let san = match numbered.strip_prefix('.') {
+                    Some(rest) => rest.trim_start_matches('.'),
+                    None => word.as_str(),
+                };
+                if san.is_empty() {
+                    continue;
+                }
+
+                let current = match position.take() {
+                    Some(position) => position,
+                    None => start_position(&tags)?,
+                };
+                let Some((start, end, promotion)) = current.parse_san(san) else {
+                    return Err(format!("illegal move {san:?} after {} moves", moves.len()));
+                };
+                position = Some(current.play(start, end, promotion));
+                moves.push((start, end, promotion));
+                annotations.push(Annotations::default());
+            }
+        }
+    }
+
+    Ok(PgnGame {
+        start: start_position(&tags)?,
+        moves,
+        annotations,
+    })
+}
+
+// The position from the `FEN` tag, or the usual one.
+fn start_position(tags: &[(String, String)]) -> Result<Position, String> {
+    let fen = tags
+        .iter()
+        .find(|(name, _)| name == "FEN")
+        .map_or(START_FEN, |(_, fen)| fen.as_str());
+    parse_fen(fen).ok_or_else(|| format!("invalid FEN {fen:?}"))
+}
+
+/// Today's date in the `YYYY.MM.DD` form of the `Date` tag.
+pub fn today() -> String {
+    let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+        return "????.??.??".to_string();
+    };
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's `civil_from_days`.
+    let days = (now.as_secs() / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_tags_and_numbered_moves() {
+        let tags = [
+            ("Event", "Casual game".to_string()),
+            ("White", "A \"quoted\" name".to_string()),
+        ];
+        let pgn = write_pgn(&tags, None, &["e4", "e5", "Qh5"], &[], "*");
+        assert_eq!(
+            pgn,
+            "[Event \"Casual game\"]\n[White \"A \\\"quoted\\\" name\"]\n\n1. e4 e5 2. Qh5 *\n"
+        );
+    }
+
+    #[test]
+    fn keeps_movetext_lines_short() {
+        let moves = vec!["Nf3"; 60];
+        let pgn = write_pgn(&[], None, &moves, &[], "1/2-1/2");
+        assert!(pgn.lines().all(|line| line.len() < 80));
+        assert!(pgn.trim_end().ends_with("Nf3 1/2-1/2"));
+    }
+
+    #[test]
+    fn dates_look_like_tags() {
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert_eq!(&date[4..5], ".");
+        assert_eq!(&date[7..8], ".");
+    }
+
+    #[test]
+    fn reads_castling_written_with_zeros() {
+        let game = read_pgn("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 *").unwrap();
+        assert_eq!(game.moves.len(), 7);
+        assert_eq!(game.moves[6], ((4, 0), (6, 0), None));
+    }
+
+    #[test]
+    fn annotations_survive_a_round_trip() {
+        let marked = Annotations {
+            arrows: vec![((4, 1), (4, 3), MarkColor::Green)],
+            squares: vec![((3, 4), MarkColor::Yellow)],
+        };
+        let annotations = [&Annotations::default(), &Annotations::default(), &marked];
+        let pgn = write_pgn(&[], None, &["e4", "e5"], &annotations, "*");
+        assert!(pgn.contains("1. e4 e5 {"), "{pgn}");
+
+        let game = read_pgn(&pgn).unwrap();
+        assert_eq!(game.annotations[2], marked);
+        assert_eq!(game.annotations[1], Annotations::default());
+    }
+
+    #[test]
+    fn skips_arrows_with_squares_that_are_not_ascii() {
+        let game = read_pgn("1. e4 {[%cal Gaé1,Rd2d4] [%csl Yé1]} e5 *").unwrap();
+        assert_eq!(game.annotations[1].arrows, [((3, 1), (3, 3), MarkColor::Red)]);
+        assert!(game.annotations[1].squares.is_empty());
+    }
+
+    #[test]
+    fn reads_move_numbers_run_together_with_moves() {
+        let game = read_pgn("1.e4 e5 2.Nf3 2...Nc6 *").unwrap();
+        assert_eq!(game.moves.len(), 4);
+    }
+}
@@ -0,0 +1,198 @@
+// Premoves: moves queued by a human during the engine's turn. Each one is played as soon as the
+// turn comes back, if it is legal then. An illegal one cancels the rest of the queue.
+
+use bevy::prelude::*;
+
+use crate::{
+    board::{TILE_SIZE, get_world_position},
+    chess::{is_legal_move, is_possible_premove, make_move},
+    components::{Piece, PieceColor, PremoveHighlight, Square},
+    events::{MoveRequestedEvent, NewGameEvent},
+    resources::{BoardOrientation, GameState, MoveHistory, Players},
+    states::InGame,
+    theme::Theme,
+};
+
+pub struct PremovePlugin;
+
+impl Plugin for PremovePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Premoves>()
+            .add_observer(on_new_game)
+            .add_systems(
+                Update,
+                (
+                    cancel_premoves_system,
+                    play_premove_system.after(cancel_premoves_system),
+                    highlight_premoves_system.after(play_premove_system),
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Premoves {
+    // Queued moves, played in order.
+    pub moves: Vec<((u8, u8), (u8, u8))>,
+    // The piece picked for the next premove, by where it will stand after the queued ones.
+    pub selected: Option<(u8, u8)>,
+}
+
+impl Premoves {
+    /// Handles a click on a square while the opponent is to move. Works on the board as it will be
+    /// after the queued premoves, so a piece can be premoved more than once.
+    pub fn click(&mut self, square: (u8, u8), board: &[(Piece, Square)], color: PieceColor) {
+        let mut board = board.to_vec();
+        for (start, end) in &self.moves {
+            make_move(&mut board, *start, *end, None, None);
+        }
+        let own_piece = board
+            .iter()
+            .find(|(piece, s)| piece.color == color && (s.x, s.y) == square)
+            .map(|(piece, _)| *piece);
+
+        match (self.selected, own_piece) {
+            (Some(selected), _) if selected == square => self.selected = None,
+            (_, Some(_)) => self.selected = Some(square),
+            (Some(selected), None) => {
+                let piece = board
+                    .iter()
+                    .find(|(_, s)| (s.x, s.y) == selected)
+                    .map(|(piece, _)| *piece);
+                if piece.is_some_and(|piece| is_possible_premove(&piece, selected, square)) {
+                    self.moves.push((selected, square));
+                }
+                self.selected = None;
+            }
+            // A click anywhere else takes back all premoves.
+            (None, None) => self.moves.clear(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.moves.clear();
+        self.selected = None;
+    }
+}
+
+fn on_new_game(_event: On<NewGameEvent>, mut premoves: ResMut<Premoves>) {
+    premoves.clear();
+}
+
+// Taking back a move or the end of the game drops the queue.
+fn cancel_premoves_system(
+    history: Res<MoveHistory>,
+    game_state: Res<GameState>,
+    mut premoves: ResMut<Premoves>,
+    mut seen: Local<usize>,
+) {
+    let undone = history.moves.len() < *seen;
+    *seen = history.moves.len();
+    if (undone || game_state.result.is_some())
+        && (!premoves.moves.is_empty() || premoves.selected.is_some())
+    {
+        premoves.clear();
+    }
+}
+
+fn play_premove_system(
+    mut commands: Commands,
+    mut premoves: ResMut<Premoves>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    piece_query: Query<(&Piece, &Square)>,
+) {
+    if !game_state.is_changed()
+        || premoves.moves.is_empty()
+        || !players.is_human(game_state.turn)
+        || game_state.result.is_some()
+    {
+        return;
+    }
+
+    let (start, end) = premoves.moves.remove(0);
+    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+    let legal = board.iter().any(|(piece, square)| {
+        (square.x, square.y) == start
+            && piece.color == game_state.turn
+            && is_legal_move(piece, start, end, &board, game_state.en_passant_target)
+    });
+
+    if legal {
+        commands.trigger(MoveRequestedEvent {
+            start,
+            end,
+            promotion: None,
+        });
+    } else {
+        premoves.clear();
+    }
+}
+
+fn highlight_premoves_system(
+    mut commands: Commands,
+    premoves: Res<Premoves>,
+    theme: Res<Theme>,
+    orientation: Res<BoardOrientation>,
+    highlight_query: Query<Entity, With<PremoveHighlight>>,
+) {
+    if !premoves.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for entity in highlight_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let squares = premoves
+        .moves
+        .iter()
+        .flat_map(|(start, end)| [*start, *end])
+        .chain(premoves.selected);
+    for (x, y) in squares {
+        commands.spawn((
+            Sprite {
+                color: theme.premove,
+                custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
+                ..default()
+            },
+            Transform::from_translation(get_world_position(
+                x as usize,
+                y as usize,
+                0.55,
+                &orientation,
+            )),
+            Square { x, y },
+            PremoveHighlight,
+            DespawnOnExit(InGame),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{START_FEN, parse_fen};
+
+    #[test]
+    fn premoves_build_on_each_other() {
+        let board = parse_fen(START_FEN).unwrap().board;
+        let mut premoves = Premoves::default();
+
+        premoves.click((4, 1), &board, PieceColor::White);
+        premoves.click((4, 3), &board, PieceColor::White);
+        // The pawn is picked up again from where the first premove leaves it.
+        premoves.click((4, 3), &board, PieceColor::White);
+        premoves.click((4, 4), &board, PieceColor::White);
+        assert_eq!(premoves.moves, [((4, 1), (4, 3)), ((4, 3), (4, 4))]);
+        assert_eq!(premoves.selected, None);
+
+        // A knight can't get there, so nothing is queued.
+        premoves.click((6, 0), &board, PieceColor::White);
+        premoves.click((6, 3), &board, PieceColor::White);
+        assert_eq!(premoves.moves.len(), 2);
+
+        premoves.click((0, 4), &board, PieceColor::White);
+        assert!(premoves.moves.is_empty());
+    }
+}
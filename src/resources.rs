@@ -1,10 +1,21 @@
 use bevy::prelude::*;
-use crate::components::PieceColor;
+use crate::{
+    chess::{Position, START_FEN, parse_fen},
+    clock::TimeControl,
+    components::PieceColor,
+    pgn::{PgnGame, read_pgn},
+    settings::UserSettings,
+    uci::UciConfig,
+};
 
 #[derive(Resource)]
 pub struct GameState {
     pub turn: PieceColor,
     pub en_passant_target: Option<(u8, u8)>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    // Set once the game is over. No more moves are played after that.
+    pub result: Option<GameResult>,
 }
 
 impl Default for GameState {
@@ -12,6 +23,634 @@ impl Default for GameState {
         Self {
             turn: PieceColor::White,
             en_passant_target: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            result: None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameEndReason {
+    Checkmate,
+    Stalemate,
+    Resignation,
+    Timeout,
+    // The flag fell, but the opponent has too little material left to ever mate.
+    TimeoutVsInsufficientMaterial,
+    InsufficientMaterial,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    // Offered by one player and accepted by the other.
+    Agreement,
+    // The other player left a game over the network.
+    Abandonment,
+}
+
+impl GameEndReason {
+    /// Completes "White wins ..." or "Draw ...".
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Checkmate => "by checkmate",
+            Self::Stalemate => "by stalemate",
+            Self::Resignation => "by resignation",
+            Self::Timeout => "on time",
+            Self::TimeoutVsInsufficientMaterial => "on time with insufficient mating material",
+            Self::InsufficientMaterial => "by insufficient material",
+            Self::FiftyMoveRule => "by the fifty-move rule",
+            Self::ThreefoldRepetition => "by threefold repetition",
+            Self::Agreement => "by agreement",
+            Self::Abandonment => "as the other player left",
+        }
+    }
+
+    /// Returns true if the game ended for a reason other than the position on the board, so
+    /// taking back moves doesn't change the result.
+    pub fn is_off_the_board(self) -> bool {
+        matches!(
+            self,
+            Self::Resignation
+                | Self::Timeout
+                | Self::TimeoutVsInsufficientMaterial
+                | Self::Agreement
+                | Self::Abandonment
+        )
+    }
+
+    /// The value of the PGN `Termination` tag.
+    pub fn termination(self) -> &'static str {
+        match self {
+            Self::Timeout | Self::TimeoutVsInsufficientMaterial => "time forfeit",
+            Self::Abandonment => "abandoned",
+            _ => "normal",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GameResult {
+    // None for a draw.
+    pub winner: Option<PieceColor>,
+    pub reason: GameEndReason,
+}
+
+impl GameResult {
+    pub fn describe(&self) -> String {
+        match self.winner {
+            Some(PieceColor::White) => format!("White wins {}", self.reason.describe()),
+            Some(PieceColor::Black) => format!("Black wins {}", self.reason.describe()),
+            None => format!("Draw {}", self.reason.describe()),
+        }
+    }
+
+    /// The result as written in PGN.
+    pub fn pgn(&self) -> &'static str {
+        match self.winner {
+            Some(PieceColor::White) => "1-0",
+            Some(PieceColor::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayerKind {
+    Human,
+    Engine,
+    // The player at the other end of a network connection.
+    Remote,
+}
+
+// Who is in control of each side. Only humans may move pieces with the mouse.
+#[derive(Resource)]
+pub struct Players {
+    pub white: PlayerKind,
+    pub black: PlayerKind,
+}
+
+impl Players {
+    pub fn get(&self, color: PieceColor) -> PlayerKind {
+        match color {
+            PieceColor::White => self.white,
+            PieceColor::Black => self.black,
+        }
+    }
+
+    pub fn is_human(&self, color: PieceColor) -> bool {
+        self.get(color) == PlayerKind::Human
+    }
+
+    pub fn has_remote(&self) -> bool {
+        self.white == PlayerKind::Remote || self.black == PlayerKind::Remote
+    }
+
+    /// Whether the game here is only watched, with both players at the other end of the network.
+    pub fn is_observing(&self) -> bool {
+        self.white == PlayerKind::Remote && self.black == PlayerKind::Remote
+    }
+}
+
+impl Default for Players {
+    fn default() -> Self {
+        Self {
+            white: PlayerKind::Human,
+            black: PlayerKind::Human,
+        }
+    }
+}
+
+// The colors of arrows and marked squares, as in the PGN `[%cal]` and `[%csl]` commands.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MarkColor {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl MarkColor {
+    pub fn letter(self) -> char {
+        match self {
+            MarkColor::Green => 'G',
+            MarkColor::Red => 'R',
+            MarkColor::Yellow => 'Y',
+            MarkColor::Blue => 'B',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter {
+            'G' => Some(MarkColor::Green),
+            'R' => Some(MarkColor::Red),
+            'Y' => Some(MarkColor::Yellow),
+            'B' => Some(MarkColor::Blue),
+            _ => None,
+        }
+    }
+}
+
+// The start and end square of an arrow, and its color.
+pub type Arrow = ((u8, u8), (u8, u8), MarkColor);
+
+// Arrows and marked squares drawn on a position.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Annotations {
+    pub arrows: Vec<Arrow>,
+    pub squares: Vec<((u8, u8), MarkColor)>,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        self.arrows.is_empty() && self.squares.is_empty()
+    }
+
+    /// Adds the arrow, or takes it away if it is already there in that color. Another color
+    /// replaces it.
+    pub fn toggle_arrow(&mut self, start: (u8, u8), end: (u8, u8), color: MarkColor) {
+        let existing = self
+            .arrows
+            .iter()
+            .position(|arrow| (arrow.0, arrow.1) == (start, end));
+        match existing {
+            Some(i) if self.arrows[i].2 == color => {
+                self.arrows.remove(i);
+            }
+            Some(i) => self.arrows[i].2 = color,
+            None => self.arrows.push((start, end, color)),
+        }
+    }
+
+    /// Like `toggle_arrow`, for a marked square.
+    pub fn toggle_square(&mut self, square: (u8, u8), color: MarkColor) {
+        let existing = self.squares.iter().position(|mark| mark.0 == square);
+        match existing {
+            Some(i) if self.squares[i].1 == color => {
+                self.squares.remove(i);
+            }
+            Some(i) => self.squares[i].1 = color,
+            None => self.squares.push((square, color)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MoveRecord {
+    pub start: (u8, u8),
+    pub end: (u8, u8),
+    pub san: String,
+    // The position right after the move.
+    pub position: Position,
+    // Drawn on that position.
+    pub annotations: Annotations,
+}
+
+// Every move of the game so far, and which of the positions is shown on the board.
+#[derive(Resource, Default)]
+pub struct MoveHistory {
+    // The position before the first move. Taken when the first move is made.
+    pub start: Option<Position>,
+    pub moves: Vec<MoveRecord>,
+    // Moves that were taken back, the most recent last. Cleared by any new move.
+    pub redo: Vec<MoveRecord>,
+    // The number of moves played in the position on display, or None for the live position.
+    // Historical positions are read-only.
+    pub viewing: Option<usize>,
+    // Drawn on the position before the first move.
+    pub start_annotations: Annotations,
+}
+
+impl MoveHistory {
+    /// The position after the given number of moves.
+    pub fn position(&self, ply: usize) -> Option<&Position> {
+        match ply {
+            0 => self.start.as_ref(),
+            _ => self.moves.get(ply - 1).map(|record| &record.position),
+        }
+    }
+
+    pub fn is_browsing(&self) -> bool {
+        self.viewing.is_some()
+    }
+
+    /// The live position.
+    pub fn current(&self) -> Option<&Position> {
+        self.position(self.moves.len())
+    }
+
+    /// The number of moves played in the position on display.
+    pub fn shown_ply(&self) -> usize {
+        self.viewing.unwrap_or(self.moves.len())
+    }
+
+    /// The arrows and marked squares of the position after the given number of moves.
+    pub fn annotations(&self, ply: usize) -> Option<&Annotations> {
+        match ply {
+            0 => Some(&self.start_annotations),
+            _ => self.moves.get(ply - 1).map(|record| &record.annotations),
+        }
+    }
+
+    pub fn annotations_mut(&mut self, ply: usize) -> Option<&mut Annotations> {
+        match ply {
+            0 => Some(&mut self.start_annotations),
+            _ => self
+                .moves
+                .get_mut(ply - 1)
+                .map(|record| &mut record.annotations),
+        }
+    }
+
+    /// Shows the position after the given number of moves; the last one is the live position.
+    pub fn view(&mut self, ply: usize) {
+        self.viewing = (ply < self.moves.len()).then_some(ply);
+    }
+}
+
+// Where the selected piece can go, as shown by the legal move highlights.
+#[derive(Resource, Default)]
+pub struct LegalMoves {
+    pub piece: Option<Entity>,
+    pub squares: Vec<(u8, u8)>,
+}
+
+// Which side of the board is drawn at the bottom of the screen.
+#[derive(Resource)]
+pub struct BoardOrientation {
+    pub bottom: PieceColor,
+    // In games between two humans at one screen, turn the board to the side to move after each move.
+    pub auto_flip: bool,
+}
+
+impl Default for BoardOrientation {
+    fn default() -> Self {
+        Self {
+            bottom: PieceColor::White,
+            auto_flip: false,
+        }
+    }
+}
+
+impl BoardOrientation {
+    /// Reads `--orientation <white|black>` and `--auto-flip` from the command line.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut orientation = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--orientation" => match args.next().as_deref() {
+                    Some("white") => orientation.bottom = PieceColor::White,
+                    Some("black") => orientation.bottom = PieceColor::Black,
+                    other => warn!("Unknown orientation {other:?}"),
+                },
+                "--auto-flip" => orientation.auto_flip = true,
+                _ => {}
+            }
+        }
+        orientation
+    }
+
+    pub fn is_flipped(&self) -> bool {
+        self.bottom == PieceColor::Black
+    }
+}
+
+// Optional parts of the board display.
+#[derive(Resource)]
+pub struct DisplayOptions {
+    // File letters and rank numbers along the edges of the board.
+    pub show_coordinates: bool,
+    // Dots on the squares the selected piece can go to.
+    pub show_legal_moves: bool,
+    // How fast pieces glide to their square. At 0 they jump there.
+    pub animation_speed: f32,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            show_coordinates: true,
+            show_legal_moves: true,
+            animation_speed: 1.0,
+        }
+    }
+}
+
+impl DisplayOptions {
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        Self {
+            show_coordinates: settings.show_coordinates,
+            show_legal_moves: settings.show_legal_moves,
+            animation_speed: settings.animation_speed,
+        }
+    }
+}
+
+// Loudness of the sound effects, from 0 to 1.
+#[derive(Resource)]
+pub struct SoundSettings {
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        Self {
+            volume: 0.7,
+            muted: false,
+        }
+    }
+}
+
+impl SoundSettings {
+    /// Reads `--volume <0..1>` and `--mute` from the command line, which take precedence over the
+    /// saved volume.
+    pub fn from_args(args: impl IntoIterator<Item = String>, user_settings: &UserSettings) -> Self {
+        let mut settings = Self {
+            volume: user_settings.volume,
+            ..default()
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--volume" => match args.next().and_then(|volume| volume.parse::<f32>().ok()) {
+                    Some(volume) => settings.volume = volume.clamp(0.0, 1.0),
+                    None => warn!("--volume needs a number from 0 to 1"),
+                },
+                "--mute" => settings.muted = true,
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+// The choices for the next game, made on the setup screen. The command line sets the defaults.
+#[derive(Resource, Clone, Debug)]
+pub struct GameSetup {
+    pub opponent: PlayerKind,
+    // The human's color against the engine, or the side at the bottom between two humans.
+    // None picks one at random.
+    pub color: Option<PieceColor>,
+    // See `TimeControl::parse`. None for an untimed game.
+    pub time_control: Option<String>,
+    // FEN of the start position, or None for the usual one.
+    pub start_fen: Option<String>,
+    // The position given with `--fen`, offered as the alternative start position.
+    pub custom_fen: Option<String>,
+    // The game given with `--pgn`, which the next game carries on from. Used once.
+    pub loaded_game: Option<PgnGame>,
+    // Both sides are played elsewhere, and the game is only watched here.
+    pub observing: bool,
+}
+
+impl GameSetup {
+    /// Reads `--time-control <spec>`, `--fen <fen>`, `--pgn <file>` and
+    /// `--orientation <white|black>` from the command line, which take precedence over the saved
+    /// settings. Against an engine with a color of its own, the human gets the other one.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+        uci_config: &UciConfig,
+        settings: &UserSettings,
+    ) -> Self {
+        let mut setup = Self {
+            opponent: PlayerKind::Human,
+            color: Some(settings.orientation),
+            time_control: settings.time_control.clone(),
+            start_fen: None,
+            custom_fen: None,
+            loaded_game: None,
+            observing: false,
+        };
+        if uci_config.path.is_some()
+            && let Some(engine_color) = uci_config.engine_color
+        {
+            setup.opponent = PlayerKind::Engine;
+            setup.color = Some(match engine_color {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White,
+            });
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--orientation" if setup.opponent == PlayerKind::Human => {
+                    match args.next().as_deref() {
+                        Some("white") => setup.color = Some(PieceColor::White),
+                        Some("black") => setup.color = Some(PieceColor::Black),
+                        _ => {}
+                    }
+                }
+                "--time-control" => {
+                    let spec = args.next().unwrap_or_default();
+                    if TimeControl::parse(&spec).is_some() {
+                        setup.time_control = Some(spec);
+                    } else {
+                        warn!("Unknown time control {spec:?}");
+                    }
+                }
+                "--fen" => {
+                    let fen = args.next().unwrap_or_default();
+                    if parse_fen(&fen).is_some() {
+                        setup.start_fen = Some(fen.clone());
+                        setup.custom_fen = Some(fen);
+                    } else {
+                        warn!("Invalid FEN {fen:?}");
+                    }
+                }
+                "--pgn" => {
+                    let path = args.next().unwrap_or_default();
+                    match std::fs::read_to_string(&path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|text| read_pgn(&text))
+                    {
+                        Ok(game) => setup.loaded_game = Some(game),
+                        Err(err) => warn!("Could not load {path}: {err}"),
+                    }
+                }
+                _ => {}
+            }
+        }
+        setup
+    }
+
+    /// The position the game starts from.
+    pub fn start_position(&self) -> Position {
+        self.start_fen
+            .as_deref()
+            .and_then(parse_fen)
+            .or_else(|| parse_fen(START_FEN))
+            .expect("the standard start position is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Piece, PieceKind, Square};
+
+    fn position(fullmove_number: u32) -> Position {
+        let king = |color, x, y| {
+            let piece = Piece {
+                color,
+                kind: PieceKind::King,
+                has_moved: false,
+                start_pos: (x, y),
+            };
+            (piece, Square { x, y })
+        };
+        Position {
+            board: vec![king(PieceColor::White, 4, 0), king(PieceColor::Black, 4, 7)],
+            turn: PieceColor::White,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number,
+        }
+    }
+
+    fn record(fullmove_number: u32) -> MoveRecord {
+        MoveRecord {
+            start: (4, 0),
+            end: (4, 1),
+            san: "Ke2".to_string(),
+            position: position(fullmove_number),
+            annotations: Annotations::default(),
+        }
+    }
+
+    #[test]
+    fn looks_up_positions_by_ply() {
+        let history = MoveHistory {
+            start: Some(position(1)),
+            moves: vec![record(2), record(3)],
+            ..default()
+        };
+        assert_eq!(history.position(0).unwrap().fullmove_number, 1);
+        assert_eq!(history.position(2).unwrap().fullmove_number, 3);
+        assert!(history.position(3).is_none());
+    }
+
+    #[test]
+    fn viewing_the_last_position_returns_to_the_game() {
+        let mut history = MoveHistory {
+            start: Some(position(1)),
+            moves: vec![record(2), record(3)],
+            ..default()
+        };
+        history.view(0);
+        assert!(history.is_browsing());
+        assert_eq!(history.viewing, Some(0));
+        history.view(2);
+        assert!(!history.is_browsing());
+    }
+
+    #[test]
+    fn the_current_position_follows_undo() {
+        let mut history = MoveHistory {
+            start: Some(position(1)),
+            moves: vec![record(2), record(3)],
+            ..default()
+        };
+        assert_eq!(history.current().unwrap().fullmove_number, 3);
+        let record = history.moves.pop().unwrap();
+        history.redo.push(record);
+        assert_eq!(history.current().unwrap().fullmove_number, 2);
+    }
+
+    #[test]
+    fn only_results_from_the_board_are_taken_back() {
+        assert!(GameEndReason::Timeout.is_off_the_board());
+        assert!(GameEndReason::TimeoutVsInsufficientMaterial.is_off_the_board());
+        assert!(GameEndReason::Resignation.is_off_the_board());
+        assert!(GameEndReason::Agreement.is_off_the_board());
+        assert!(GameEndReason::Abandonment.is_off_the_board());
+        assert!(!GameEndReason::Checkmate.is_off_the_board());
+        assert!(!GameEndReason::ThreefoldRepetition.is_off_the_board());
+    }
+
+    #[test]
+    fn setup_follows_the_command_line() {
+        let args = [
+            "--time-control",
+            "5+3",
+            "--fen",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+        ];
+        let setup = GameSetup::from_args(
+            args.map(String::from),
+            &UciConfig::default(),
+            &UserSettings::default(),
+        );
+        assert_eq!(setup.opponent, PlayerKind::Human);
+        assert_eq!(setup.time_control.as_deref(), Some("5+3"));
+        assert_eq!(setup.start_position().fen(), args[3]);
+
+        let engine = UciConfig {
+            path: Some("engine".into()),
+            ..default()
+        };
+        let args = ["--time-control", "soon", "--orientation", "white"];
+        let setup = GameSetup::from_args(args.map(String::from), &engine, &UserSettings::default());
+        assert_eq!(setup.opponent, PlayerKind::Engine);
+        assert_eq!(setup.color, Some(PieceColor::White));
+        assert_eq!(setup.time_control, None);
+        assert_eq!(setup.start_position().fen(), START_FEN);
+    }
+
+    #[test]
+    fn sound_settings_from_the_command_line() {
+        let settings = SoundSettings::from_args(
+            ["--volume", "1.5", "--mute"].map(String::from),
+            &UserSettings::default(),
+        );
+        assert_eq!(settings.volume, 1.0);
+        assert!(settings.muted);
+
+        let settings = SoundSettings::from_args(
+            ["--volume", "loud"].map(String::from),
+            &UserSettings::default(),
+        );
+        assert_eq!(settings.volume, SoundSettings::default().volume);
+        assert!(!settings.muted);
+    }
+}
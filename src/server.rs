@@ -0,0 +1,1066 @@
+// A game server with no window, run with `--server [port]` instead of the board. It pairs players
+// in the order they connect, checks every move, runs the clocks and saves each finished game as
+// PGN. Players connect with `--join <address>` and speak the protocol of `net.rs`, in which the
+// server takes the host's part for both of them.
+//
+// A player who leaves during a game resigns it, and one who leaves before the first move calls it
+// off. Either way nobody is paired again until they say hello once more, which the client does when
+// it leaves the game. A finished game is kept while both players are still looking at it, so they
+// can ask for a rematch; once one of them has moved on, a rematch is called off.
+//
+// Spectators, who connect with `--watch`, follow the longest running game, or the next one to
+// start.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    iter::Peekable,
+    net::{Shutdown, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*};
+
+use crate::{
+    chess::{Move, Position, START_FEN, parse_fen, to_san},
+    clock::{Clock, TimeControl, timeout_result},
+    components::PieceColor,
+    game_over::decided_result,
+    net::{NetMessage, PROTOCOL_VERSION, WRITE_TIMEOUT, optional_port},
+    pgn::{today, write_pgn},
+    resources::{GameEndReason, GameResult},
+};
+
+// How often the server looks at its connections and clocks.
+const TICK: Duration = Duration::from_millis(10);
+
+#[derive(Resource, Clone, Debug)]
+pub struct ServerConfig {
+    // The port to listen on, or 0 for any. Holds the one bound once listening.
+    pub port: u16,
+    // See `TimeControl::parse`. None for untimed games.
+    pub time_control: Option<String>,
+    // FEN of the start position, or None for the usual one.
+    pub start_fen: Option<String>,
+    // Where finished games are saved.
+    pub games_dir: PathBuf,
+}
+
+impl ServerConfig {
+    /// Reads `--server [port]`, `--time-control <spec>`, `--fen <fen>` and `--games <directory>`
+    /// from the command line. Returns None without `--server`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Option<Self> {
+        let mut port = None;
+        let mut config = Self {
+            port: 0,
+            time_control: None,
+            start_fen: None,
+            games_dir: PathBuf::from("games"),
+        };
+        let mut args: Peekable<_> = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--server" => port = Some(optional_port(&mut args)),
+                "--time-control" => match args.next() {
+                    Some(spec) if TimeControl::parse(&spec).is_some() => {
+                        config.time_control = Some(spec)
+                    }
+                    other => warn!("Invalid time control {other:?}"),
+                },
+                "--fen" => match args.next() {
+                    Some(fen) if parse_fen(&fen).is_some() => config.start_fen = Some(fen),
+                    other => warn!("Invalid FEN {other:?}"),
+                },
+                "--games" => match args.next() {
+                    Some(dir) => config.games_dir = PathBuf::from(dir),
+                    None => warn!("--games needs a directory"),
+                },
+                _ => {}
+            }
+        }
+        config.port = port?;
+        Some(config)
+    }
+
+    fn start_position(&self) -> Position {
+        self.start_fen
+            .as_deref()
+            .and_then(parse_fen)
+            .or_else(|| parse_fen(START_FEN))
+            .expect("the start position is valid")
+    }
+}
+
+/// Runs the server until the process is stopped.
+pub fn run(config: ServerConfig) {
+    App::new()
+        .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(TICK)))
+        .add_plugins(LogPlugin::default())
+        .insert_resource(config)
+        .add_plugins(ServerPlugin)
+        .run();
+}
+
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_listening).add_systems(
+            Update,
+            (
+                server_events_system,
+                pair_players_system.after(server_events_system),
+                server_clock_system.after(server_events_system),
+            ),
+        );
+    }
+}
+
+type ClientId = u64;
+
+// What the network threads pass on.
+enum ServerEvent {
+    // A new connection, with the stream to write to.
+    Connected(ClientId, TcpStream),
+    Message(ClientId, NetMessage),
+    Closed(ClientId),
+}
+
+struct Client {
+    stream: TcpStream,
+    // The peer address, which also names the player in saved games.
+    name: String,
+    // The game the player is in, finished or not, or the one a spectator watches.
+    game: Option<usize>,
+    spectator: bool,
+}
+
+struct ServerGame {
+    white: ClientId,
+    black: ClientId,
+    // The white and black clocks, for a timed game.
+    clocks: Option<(Clock, Clock)>,
+    // Every position so far, starting with the start position.
+    positions: Vec<Position>,
+    played: Vec<Move>,
+    // The moves in SAN.
+    moves: Vec<String>,
+    // The side whose draw offer stands, until the other side moves.
+    draw_offer: Option<PieceColor>,
+    // Set as the game ends, when it is saved.
+    result: Option<GameResult>,
+    spectators: Vec<ClientId>,
+}
+
+impl ServerGame {
+    fn new(white: ClientId, black: ClientId, config: &ServerConfig) -> Self {
+        let clocks = config
+            .time_control
+            .as_deref()
+            .and_then(TimeControl::parse)
+            .map(|control| (Clock::new(&control), Clock::new(&control)));
+        Self {
+            white,
+            black,
+            clocks,
+            positions: vec![config.start_position()],
+            played: Vec::new(),
+            moves: Vec::new(),
+            draw_offer: None,
+            result: None,
+            spectators: Vec::new(),
+        }
+    }
+
+    fn player(&self, color: PieceColor) -> ClientId {
+        match color {
+            PieceColor::White => self.white,
+            PieceColor::Black => self.black,
+        }
+    }
+
+    fn color_of(&self, client: ClientId) -> PieceColor {
+        if client == self.white {
+            PieceColor::White
+        } else {
+            PieceColor::Black
+        }
+    }
+
+    fn position(&self) -> &Position {
+        self.positions.last().expect("a game has a start position")
+    }
+
+    fn clock_mut(&mut self, color: PieceColor) -> Option<&mut Clock> {
+        self.clocks.as_mut().map(|(white, black)| match color {
+            PieceColor::White => white,
+            PieceColor::Black => black,
+        })
+    }
+
+    fn play(&mut self, (start, end, promotion): Move, control: Option<&TimeControl>) {
+        let position = self.position();
+        let mover = position.turn;
+        let san = to_san(&position.board, start, end, promotion, position.en_passant);
+        let next = position.play(start, end, promotion);
+        self.positions.push(next);
+        self.played.push((start, end, promotion));
+        self.moves.push(san);
+
+        if let Some(control) = control
+            && let Some(clock) = self.clock_mut(mover)
+        {
+            clock.complete_move(control);
+        }
+        if self.draw_offer.is_some_and(|color| color != mover) {
+            self.draw_offer = None;
+        }
+        let positions: Vec<&Position> = self.positions.iter().collect();
+        self.result = decided_result(&positions);
+    }
+
+    // The game so far, for a spectator.
+    fn observe_message(&self, config: &ServerConfig) -> NetMessage {
+        NetMessage::Observe {
+            time_control: config.time_control.clone(),
+            fen: self.positions[0].fen(),
+            moves: self.played.clone(),
+        }
+    }
+
+    // How the game ended, for spectators whose board cannot tell.
+    fn ending_message(&self) -> Option<NetMessage> {
+        let result = self.result?;
+        match result.reason {
+            GameEndReason::Resignation => result
+                .winner
+                .map(|winner| NetMessage::Resigned(opposite(winner))),
+            GameEndReason::Agreement => Some(NetMessage::DrawAccept),
+            GameEndReason::Timeout | GameEndReason::TimeoutVsInsufficientMaterial => {
+                Some(NetMessage::Flag(self.position().turn))
+            }
+            _ => None,
+        }
+    }
+
+    fn clock_message(&self) -> Option<NetMessage> {
+        self.clocks
+            .as_ref()
+            .map(|(white, black)| NetMessage::Clock {
+                ply: self.moves.len(),
+                white: white.remaining,
+                black: black.remaining,
+            })
+    }
+}
+
+#[derive(Resource)]
+struct Server {
+    events: Mutex<Receiver<ServerEvent>>,
+    clients: HashMap<ClientId, Client>,
+    // Players without an opponent, the one waiting longest first.
+    waiting: VecDeque<ClientId>,
+    games: HashMap<usize, ServerGame>,
+    next_game: usize,
+    time_control: Option<TimeControl>,
+}
+
+impl Server {
+    // Writes wait at most `WRITE_TIMEOUT`. A player who doesn't keep up is dropped: their reader
+    // thread then sees the connection close, and the writes left until then fail at once.
+    fn send(&mut self, client: ClientId, message: &NetMessage) {
+        let Some(client) = self.clients.get_mut(&client) else {
+            return;
+        };
+        let line = message.to_line();
+        if let Err(err) = writeln!(client.stream, "{line}").and_then(|_| client.stream.flush()) {
+            warn!("Could not send {line:?} to {}: {err}", client.name);
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    // Sends a message to the spectators of a game.
+    fn tell_spectators(&mut self, game: usize, message: &NetMessage) {
+        let spectators = self
+            .games
+            .get(&game)
+            .map_or(Vec::new(), |game| game.spectators.clone());
+        for spectator in spectators {
+            self.send(spectator, message);
+        }
+    }
+
+    // Sends a message to both players of a game and its spectators.
+    fn broadcast(&mut self, game: usize, message: &NetMessage) {
+        if let Some((white, black)) = self.games.get(&game).map(|game| (game.white, game.black)) {
+            self.send(white, message);
+            self.send(black, message);
+        }
+        self.tell_spectators(game, message);
+    }
+
+    // Lets spectators watch the game in progress that started first. With none, they wait for the
+    // next one.
+    fn find_games(&mut self, spectators: Vec<ClientId>, config: &ServerConfig) {
+        let running = self
+            .games
+            .iter()
+            .filter(|(_, game)| game.result.is_none())
+            .map(|(id, _)| *id)
+            .min();
+        for spectator in spectators {
+            let Some(client) = self.clients.get_mut(&spectator) else {
+                continue;
+            };
+            client.game = running;
+            let Some(game) = running.and_then(|id| self.games.get_mut(&id)) else {
+                continue;
+            };
+            game.spectators.push(spectator);
+            let messages = [Some(game.observe_message(config)), game.clock_message()];
+            for message in messages.into_iter().flatten() {
+                self.send(spectator, &message);
+            }
+        }
+    }
+
+    // The game a player is in and their side in it.
+    fn seat(&self, client: ClientId) -> Option<(usize, PieceColor)> {
+        let id = self
+            .clients
+            .get(&client)
+            .filter(|player| !player.spectator)?
+            .game?;
+        let game = self.games.get(&id)?;
+        Some((id, game.color_of(client)))
+    }
+
+    fn start_game(&mut self, white: ClientId, black: ClientId, config: &ServerConfig) {
+        let id = self.next_game;
+        self.next_game += 1;
+        let game = ServerGame::new(white, black, config);
+        let fen = game.position().fen();
+        self.games.insert(id, game);
+
+        for (client, color) in [(white, PieceColor::White), (black, PieceColor::Black)] {
+            if let Some(client) = self.clients.get_mut(&client) {
+                client.game = Some(id);
+            }
+            self.send(
+                client,
+                &NetMessage::Start {
+                    color,
+                    time_control: config.time_control.clone(),
+                    fen: fen.clone(),
+                },
+            );
+        }
+        info!(
+            "Game {id}: {} against {}",
+            self.name(white),
+            self.name(black)
+        );
+        self.watch_idle(config);
+    }
+
+    // Gives the spectators without a game one to watch.
+    fn watch_idle(&mut self, config: &ServerConfig) {
+        let idle: Vec<ClientId> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.spectator && client.game.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        self.find_games(idle, config);
+    }
+
+    fn name(&self, client: ClientId) -> String {
+        self.clients
+            .get(&client)
+            .map_or("?".to_string(), |client| client.name.clone())
+    }
+
+    // Ends a game and saves it. Its spectators move on to another.
+    fn finish(&mut self, id: usize, result: GameResult, config: &ServerConfig) {
+        let Some(game) = self.games.get_mut(&id) else {
+            return;
+        };
+        game.result = Some(result);
+        info!("Game {id}: {}", result.describe());
+
+        let game = &self.games[&id];
+        let ending = game.ending_message();
+        let names = (self.name(game.white), self.name(game.black));
+        match save_game(id, game, names, &config.games_dir) {
+            Ok(path) => info!("Game {id} saved to {}", path.display()),
+            Err(err) => error!("Could not save game {id}: {err}"),
+        }
+        if let Some(ending) = ending {
+            self.tell_spectators(id, &ending);
+        }
+
+        let spectators = self
+            .games
+            .get_mut(&id)
+            .map_or(Vec::new(), |game| std::mem::take(&mut game.spectators));
+        self.find_games(spectators, config);
+    }
+
+    // Drops a game, finished or called off.
+    fn remove_game(&mut self, id: usize, config: &ServerConfig) {
+        if let Some(game) = self.games.remove(&id) {
+            self.find_games(game.spectators, config);
+        }
+    }
+
+    // Whether the client is still in the game, finished or not.
+    fn is_seated(&self, client: ClientId, game: usize) -> bool {
+        self.clients
+            .get(&client)
+            .is_some_and(|client| !client.spectator && client.game == Some(game))
+    }
+
+    // Takes a player out of their game. An unfinished game goes to their opponent, or is called off
+    // for both if nobody has moved yet. A finished game is dropped once neither player is left in it.
+    fn quit_game(&mut self, client: ClientId, config: &ServerConfig) {
+        let Some((id, color)) = self.seat(client) else {
+            return;
+        };
+        let game = &self.games[&id];
+        let other = game.player(opposite(color));
+        let (in_progress, unplayed) = (game.result.is_none(), game.moves.is_empty());
+        if let Some(client) = self.clients.get_mut(&client) {
+            client.game = None;
+        }
+
+        if in_progress && unplayed {
+            self.send(other, &NetMessage::Cancel);
+            if let Some(other) = self.clients.get_mut(&other) {
+                other.game = None;
+            }
+            self.remove_game(id, config);
+            info!("Game {id}: called off");
+            return;
+        }
+        if in_progress {
+            self.send(other, &NetMessage::Resign);
+            self.finish(
+                id,
+                GameResult {
+                    winner: Some(opposite(color)),
+                    reason: GameEndReason::Resignation,
+                },
+                config,
+            );
+        }
+        if !self.is_seated(other, id) {
+            self.remove_game(id, config);
+        }
+    }
+
+    // A player or spectator left, or is sent away.
+    fn leave(&mut self, client: ClientId, config: &ServerConfig) {
+        self.quit_game(client, config);
+        if let Some(id) = self
+            .clients
+            .get(&client)
+            .filter(|client| client.spectator)
+            .and_then(|client| client.game)
+            && let Some(game) = self.games.get_mut(&id)
+        {
+            game.spectators.retain(|spectator| *spectator != client);
+        }
+
+        self.waiting.retain(|waiting| *waiting != client);
+        if let Some(removed) = self.clients.remove(&client) {
+            let _ = removed.stream.shutdown(Shutdown::Both);
+            info!("{} left", removed.name);
+        }
+    }
+
+    fn handle(&mut self, client: ClientId, message: NetMessage, config: &ServerConfig) {
+        // Messages already on their way from a player who was sent away.
+        let Some(spectator) = self.clients.get(&client).map(|client| client.spectator) else {
+            return;
+        };
+
+        match message {
+            // Queueing a player twice would pair them with themselves.
+            NetMessage::Hello { .. } if spectator || self.waiting.contains(&client) => {
+                warn!("Ignoring another hello from {}", self.name(client));
+                return;
+            }
+            NetMessage::Hello {
+                version,
+                watch: true,
+            } if version == PROTOCOL_VERSION => {
+                self.quit_game(client, config);
+                info!("{} is watching", self.name(client));
+                if let Some(spectator) = self.clients.get_mut(&client) {
+                    spectator.spectator = true;
+                }
+                self.find_games(vec![client], config);
+                return;
+            }
+            // A player still in a game is done with it.
+            NetMessage::Hello { version, .. } if version == PROTOCOL_VERSION => {
+                self.quit_game(client, config);
+                info!("{} is waiting for a game", self.name(client));
+                self.waiting.push_back(client);
+                return;
+            }
+            NetMessage::Hello { version, .. } => {
+                warn!(
+                    "{} speaks protocol version {version}, not {PROTOCOL_VERSION}",
+                    self.name(client)
+                );
+                self.send(client, &NetMessage::Bye);
+                self.leave(client, config);
+                return;
+            }
+            NetMessage::Bye => {
+                self.leave(client, config);
+                return;
+            }
+            _ => {}
+        }
+
+        let Some((id, color)) = self.seat(client) else {
+            return;
+        };
+        let game = &self.games[&id];
+        let opponent = game.player(opposite(color));
+        let in_progress = game.result.is_none();
+
+        match message {
+            NetMessage::Move(mv) => {
+                if !in_progress || game.position().turn != color || !game.position().is_legal(mv) {
+                    warn!("{} sent an illegal move", self.name(client));
+                    self.send(client, &NetMessage::Bye);
+                    self.leave(client, config);
+                    return;
+                }
+                let control = self.time_control.clone();
+                let Some(game) = self.games.get_mut(&id) else {
+                    return;
+                };
+                game.play(mv, control.as_ref());
+                let result = game.result;
+                let clock = game.clock_message();
+
+                self.send(opponent, &NetMessage::Move(mv));
+                self.tell_spectators(id, &NetMessage::Move(mv));
+                if let Some(clock) = clock {
+                    self.broadcast(id, &clock);
+                }
+                if let Some(result) = result {
+                    self.finish(id, result, config);
+                }
+            }
+            NetMessage::Resign if in_progress => {
+                self.send(opponent, &NetMessage::Resign);
+                self.finish(
+                    id,
+                    GameResult {
+                        winner: Some(opposite(color)),
+                        reason: GameEndReason::Resignation,
+                    },
+                    config,
+                );
+            }
+            NetMessage::DrawOffer if in_progress => {
+                if let Some(game) = self.games.get_mut(&id) {
+                    game.draw_offer = Some(color);
+                }
+                self.send(opponent, &NetMessage::DrawOffer);
+            }
+            NetMessage::DrawAccept if in_progress && game.draw_offer == Some(opposite(color)) => {
+                self.send(opponent, &NetMessage::DrawAccept);
+                self.finish(
+                    id,
+                    GameResult {
+                        winner: None,
+                        reason: GameEndReason::Agreement,
+                    },
+                    config,
+                );
+            }
+            // The player asking has already started the game with colors swapped, and the
+            // opponent follows once told. If the opponent has moved on, the rematch is called off
+            // and the player asking leaves the game too.
+            NetMessage::Rematch if !in_progress => {
+                if self.is_seated(opponent, id) {
+                    let rematch = ServerGame::new(game.black, game.white, config);
+                    self.games.insert(id, rematch);
+                    self.send(opponent, &NetMessage::Rematch);
+                    info!("Game {id}: rematch with colors swapped");
+                    self.watch_idle(config);
+                } else {
+                    self.send(client, &NetMessage::Cancel);
+                    self.quit_game(client, config);
+                }
+            }
+            NetMessage::Cancel if in_progress && game.moves.is_empty() => {
+                self.quit_game(client, config);
+            }
+            // The server keeps the time itself.
+            NetMessage::Flag(_) => {}
+            other => warn!("Ignoring {:?} from {}", other.to_line(), self.name(client)),
+        }
+    }
+}
+
+fn opposite(color: PieceColor) -> PieceColor {
+    match color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    }
+}
+
+// Writes a finished game to a new file in `dir` and returns its path.
+fn save_game(
+    id: usize,
+    game: &ServerGame,
+    (white, black): (String, String),
+    dir: &Path,
+) -> std::io::Result<PathBuf> {
+    let result = game.result.map_or("*", |result| result.pgn());
+    let termination = game
+        .result
+        .map_or("unterminated", |result| result.reason.termination());
+    let tags = [
+        ("Event", "Server game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", today()),
+        ("Round", id.to_string()),
+        ("White", white),
+        ("Black", black),
+        ("Result", result.to_string()),
+        ("Termination", termination.to_string()),
+    ];
+    let moves: Vec<&str> = game.moves.iter().map(String::as_str).collect();
+    let pgn = write_pgn(&tags, game.positions.first(), &moves, &[], result);
+
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("game-{seconds}-{id}.pgn"));
+    std::fs::write(&path, pgn)?;
+    Ok(path)
+}
+
+// Accepts connections on a background thread, with another thread reading from each.
+fn start_listening(
+    mut commands: Commands,
+    mut config: ResMut<ServerConfig>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let listener = match TcpListener::bind(("0.0.0.0", config.port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Could not listen on port {}: {err}", config.port);
+            exit.write(AppExit::error());
+            return;
+        }
+    };
+
+    // Port 0 leaves the choice to the system.
+    if let Ok(address) = listener.local_addr() {
+        config.port = address.port();
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("server-accept".into())
+        .spawn(move || {
+            for (id, stream) in (0..).zip(listener.incoming()) {
+                let reader = match stream.and_then(|stream| {
+                    stream.set_nodelay(true)?;
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    Ok((stream.try_clone()?, stream))
+                }) {
+                    Ok((reader, writer)) => {
+                        if sender.send(ServerEvent::Connected(id, writer)).is_err() {
+                            return;
+                        }
+                        reader
+                    }
+                    Err(err) => {
+                        warn!("Could not accept a connection: {err}");
+                        continue;
+                    }
+                };
+                spawn_reader(id, reader, sender.clone());
+            }
+        });
+    if let Err(err) = spawned {
+        error!("Could not start the server thread: {err}");
+        exit.write(AppExit::error());
+        return;
+    }
+
+    info!("Serving games on port {}", config.port);
+    commands.insert_resource(Server {
+        events: Mutex::new(receiver),
+        clients: HashMap::new(),
+        waiting: VecDeque::new(),
+        games: HashMap::new(),
+        next_game: 1,
+        time_control: config.time_control.as_deref().and_then(TimeControl::parse),
+    });
+}
+
+fn spawn_reader(id: ClientId, reader: TcpStream, sender: Sender<ServerEvent>) {
+    let spawned = thread::Builder::new()
+        .name(format!("server-reader-{id}"))
+        .spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else { break };
+                match NetMessage::parse(&line) {
+                    Some(message) => {
+                        if sender.send(ServerEvent::Message(id, message)).is_err() {
+                            return;
+                        }
+                    }
+                    None => warn!("Ignoring unknown message {line:?}"),
+                }
+            }
+            let _ = sender.send(ServerEvent::Closed(id));
+        });
+    if let Err(err) = spawned {
+        error!("Could not start a reader thread: {err}");
+    }
+}
+
+fn server_events_system(server: Option<ResMut<Server>>, config: Res<ServerConfig>) {
+    let Some(mut server) = server else {
+        return;
+    };
+    let events: Vec<ServerEvent> = match server.events.lock() {
+        Ok(receiver) => receiver.try_iter().collect(),
+        Err(_) => Vec::new(),
+    };
+
+    for event in events {
+        match event {
+            ServerEvent::Connected(id, stream) => {
+                let name = stream
+                    .peer_addr()
+                    .map_or(format!("player {id}"), |peer| peer.to_string());
+                info!("{name} connected");
+                server.clients.insert(
+                    id,
+                    Client {
+                        stream,
+                        name,
+                        game: None,
+                        spectator: false,
+                    },
+                );
+                server.send(
+                    id,
+                    &NetMessage::Hello {
+                        version: PROTOCOL_VERSION,
+                        watch: false,
+                    },
+                );
+            }
+            ServerEvent::Message(id, message) => server.handle(id, message, &config),
+            ServerEvent::Closed(id) => server.leave(id, &config),
+        }
+    }
+}
+
+// Pairs the players waiting longest. The first of them plays White.
+fn pair_players_system(server: Option<ResMut<Server>>, config: Res<ServerConfig>) {
+    let Some(mut server) = server else {
+        return;
+    };
+    while server.waiting.len() >= 2 {
+        let (Some(white), Some(black)) = (server.waiting.pop_front(), server.waiting.pop_front())
+        else {
+            break;
+        };
+        server.start_game(white, black, &config);
+    }
+}
+
+// Runs the clock of the side to move in each game, once the first move has been made. A flag fall
+// ends the game for both players.
+fn server_clock_system(time: Res<Time>, server: Option<ResMut<Server>>, config: Res<ServerConfig>) {
+    let Some(mut server) = server else {
+        return;
+    };
+    let Some(control) = server.time_control.clone() else {
+        return;
+    };
+
+    let mut flagged = Vec::new();
+    for (id, game) in server.games.iter_mut() {
+        if game.result.is_some() || game.moves.is_empty() {
+            continue;
+        }
+        let turn = game.position().turn;
+        let Some(clock) = game.clock_mut(turn) else {
+            continue;
+        };
+        clock.tick(&control, time.delta());
+        if clock.remaining.is_zero() {
+            flagged.push((*id, turn, timeout_result(turn, &game.position().board)));
+        }
+    }
+
+    for (id, color, result) in flagged {
+        if let Some(clock) = server.games.get(&id).and_then(ServerGame::clock_message) {
+            server.broadcast(id, &clock);
+        }
+        if let Some(game) = server.games.get(&id) {
+            let (white, black) = (game.white, game.black);
+            server.send(white, &NetMessage::Flag(color));
+            server.send(black, &NetMessage::Flag(color));
+        }
+        server.finish(id, result, &config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::parse_uci_move;
+
+    // A player connected to the server, with what the server sent them.
+    struct TestClient {
+        stream: TcpStream,
+        received: Receiver<NetMessage>,
+    }
+
+    impl TestClient {
+        fn connect(port: u16) -> Self {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let reader = stream.try_clone().unwrap();
+            let (sender, received) = mpsc::channel();
+            thread::spawn(move || {
+                for line in BufReader::new(reader).lines() {
+                    let Ok(line) = line else { break };
+                    let message = NetMessage::parse(&line).expect("a known message");
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+            Self { stream, received }
+        }
+
+        fn send(&mut self, message: &NetMessage) {
+            writeln!(self.stream, "{}", message.to_line()).unwrap();
+        }
+
+        // Runs the server until the next message for this client arrives.
+        fn receive(&self, app: &mut App) -> NetMessage {
+            for _ in 0..500 {
+                app.update();
+                if let Ok(message) = self.received.recv_timeout(Duration::from_millis(10)) {
+                    return message;
+                }
+            }
+            panic!("the server sent nothing");
+        }
+    }
+
+    fn hello() -> NetMessage {
+        NetMessage::Hello {
+            version: PROTOCOL_VERSION,
+            watch: false,
+        }
+    }
+
+    fn move_message(uci: &str) -> NetMessage {
+        NetMessage::Move(parse_uci_move(uci).unwrap())
+    }
+
+    fn server(app: &App) -> &Server {
+        app.world().resource::<Server>()
+    }
+
+    fn update_until(app: &mut App, done: impl Fn(&Server) -> bool) {
+        for _ in 0..500 {
+            app.update();
+            if done(server(app)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the server never got there");
+    }
+
+    fn games_dir(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("server-test-{test}-{}", std::process::id()))
+    }
+
+    fn start_server(games_dir: &Path) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(ServerConfig {
+                port: 0,
+                time_control: None,
+                start_fen: None,
+                games_dir: games_dir.to_path_buf(),
+            })
+            .add_plugins(ServerPlugin);
+        app.update();
+        app
+    }
+
+    // Connects two players, who then play a game with the first as White.
+    fn pair(app: &mut App) -> (TestClient, TestClient) {
+        let port = app.world().resource::<ServerConfig>().port;
+        let mut white = TestClient::connect(port);
+        assert_eq!(white.receive(app), hello());
+        white.send(&hello());
+        update_until(app, |server| server.waiting.len() == 1);
+        let mut black = TestClient::connect(port);
+        assert_eq!(black.receive(app), hello());
+        black.send(&hello());
+
+        for (client, color) in [(&white, PieceColor::White), (&black, PieceColor::Black)] {
+            match client.receive(app) {
+                NetMessage::Start { color: side, .. } => assert_eq!(side, color),
+                other => panic!("expected the game to start, got {other:?}"),
+            }
+        }
+        (white, black)
+    }
+
+    #[test]
+    fn pairs_players_and_rejects_illegal_moves() {
+        let games_dir = games_dir("illegal");
+        let mut app = start_server(&games_dir);
+        let (mut white, mut black) = pair(&mut app);
+
+        white.send(&move_message("e2e4"));
+        assert_eq!(black.receive(&mut app), move_message("e2e4"));
+        black.send(&move_message("e7e5"));
+        assert_eq!(white.receive(&mut app), move_message("e7e5"));
+
+        // The king can't go three squares, so White is sent away and loses the game.
+        white.send(&move_message("e1e4"));
+        assert_eq!(white.receive(&mut app), NetMessage::Bye);
+        assert_eq!(black.receive(&mut app), NetMessage::Resign);
+        assert_eq!(server(&app).games.len(), 1);
+        assert!(server(&app).waiting.is_empty());
+
+        // Black is only paired again once they ask.
+        black.send(&hello());
+        update_until(&mut app, |server| server.waiting == [1]);
+        assert!(server(&app).games.is_empty());
+
+        let _ = std::fs::remove_dir_all(games_dir);
+    }
+
+    #[test]
+    fn rematches_only_while_both_players_are_there() {
+        let games_dir = games_dir("rematch");
+        let mut app = start_server(&games_dir);
+        let (mut white, mut black) = pair(&mut app);
+
+        white.send(&NetMessage::Resign);
+        assert_eq!(black.receive(&mut app), NetMessage::Resign);
+        black.send(&NetMessage::Rematch);
+        assert_eq!(white.receive(&mut app), NetMessage::Rematch);
+        let game = server(&app).games.values().next().unwrap();
+        assert_eq!((game.white, game.black), (1, 0));
+
+        // Leaving before the first move calls the rematch off rather than losing it.
+        white.send(&hello());
+        assert_eq!(black.receive(&mut app), NetMessage::Cancel);
+        assert!(server(&app).games.is_empty());
+        assert_eq!(server(&app).waiting, [0]);
+
+        // With the other player gone, a rematch is turned down.
+        black.send(&hello());
+        for (client, color) in [(&white, PieceColor::White), (&black, PieceColor::Black)] {
+            match client.receive(&mut app) {
+                NetMessage::Start { color: side, .. } => assert_eq!(side, color),
+                other => panic!("expected the game to start, got {other:?}"),
+            }
+        }
+        black.send(&NetMessage::Resign);
+        assert_eq!(white.receive(&mut app), NetMessage::Resign);
+        white.send(&hello());
+        update_until(&mut app, |server| server.waiting == [0]);
+        black.send(&NetMessage::Rematch);
+        assert_eq!(black.receive(&mut app), NetMessage::Cancel);
+        assert!(server(&app).games.is_empty());
+
+        let _ = std::fs::remove_dir_all(games_dir);
+    }
+
+    #[test]
+    fn ignores_players_who_left() {
+        let (_sender, receiver) = mpsc::channel();
+        let mut server = Server {
+            events: Mutex::new(receiver),
+            clients: HashMap::new(),
+            waiting: VecDeque::new(),
+            games: HashMap::new(),
+            next_game: 1,
+            time_control: None,
+        };
+        let config = ServerConfig {
+            port: 0,
+            time_control: None,
+            start_fen: None,
+            games_dir: games_dir("left"),
+        };
+
+        server.handle(7, hello(), &config);
+        assert!(server.waiting.is_empty());
+    }
+
+    #[test]
+    fn spectators_follow_the_game_in_progress() {
+        let games_dir = games_dir("watch");
+        let mut app = start_server(&games_dir);
+        let port = app.world().resource::<ServerConfig>().port;
+        let mut spectator = TestClient::connect(port);
+        assert_eq!(spectator.receive(&mut app), hello());
+        spectator.send(&NetMessage::Hello {
+            version: PROTOCOL_VERSION,
+            watch: true,
+        });
+        update_until(&mut app, |server| {
+            server.clients.values().any(|client| client.spectator)
+        });
+
+        let (mut white, black) = pair(&mut app);
+        assert!(matches!(
+            spectator.receive(&mut app),
+            NetMessage::Observe { moves, .. } if moves.is_empty()
+        ));
+        white.send(&move_message("e2e4"));
+        assert_eq!(black.receive(&mut app), move_message("e2e4"));
+        assert_eq!(spectator.receive(&mut app), move_message("e2e4"));
+
+        white.send(&NetMessage::Resign);
+        assert_eq!(black.receive(&mut app), NetMessage::Resign);
+        assert_eq!(
+            spectator.receive(&mut app),
+            NetMessage::Resigned(PieceColor::White)
+        );
+        assert!(
+            server(&app)
+                .games
+                .values()
+                .all(|game| game.spectators.is_empty())
+        );
+
+        let _ = std::fs::remove_dir_all(games_dir);
+    }
+}
@@ -0,0 +1,485 @@
+// User settings, kept in `chess/settings.conf` in the user's config directory and changed on the
+// settings screen. The file has lines of `key = value`, and comment lines starting with `#`:
+//
+//   theme             name of the board theme
+//   volume            sound volume, from 0 to 1
+//   orientation       white or black, the side at the bottom and the human's side against an engine
+//   show_legal_moves  true or false
+//   show_coordinates  true or false
+//   animation_speed   how fast pieces glide, 1 being normal and 0 turning it off
+//   time_control      the time control offered first, see `TimeControl::parse`, or none
+//   engine            path of a UCI engine, or empty for none
+//
+// The command line options of the same things win over the file for that run, without being saved.
+
+use std::{env, fs, path::PathBuf};
+
+use bevy::{
+    input::keyboard::{Key, KeyboardInput},
+    prelude::*,
+};
+
+use crate::{
+    components::{PieceColor, SettingsAction},
+    menu::TIME_CONTROLS,
+    resources::{DisplayOptions, GameSetup, SoundSettings},
+    states::AppState,
+    theme::{Theme, Themes},
+    ui::text_button,
+};
+
+// The choices of the animation speed.
+const ANIMATION_SPEEDS: [(f32, &str); 4] =
+    [(0.0, "Off"), (0.5, "Slow"), (1.0, "Normal"), (2.0, "Fast")];
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EnginePathInput>()
+            .add_systems(OnEnter(AppState::Settings), spawn_settings_screen)
+            .add_systems(OnExit(AppState::Settings), stop_editing)
+            .add_systems(
+                Update,
+                (
+                    settings_buttons_system,
+                    engine_path_input_system,
+                    update_settings_text_system
+                        .after(settings_buttons_system)
+                        .after(engine_path_input_system),
+                )
+                    .run_if(in_state(AppState::Settings)),
+            )
+            .add_systems(
+                Update,
+                (
+                    remember_settings_system,
+                    save_settings_system.after(remember_settings_system),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct UserSettings {
+    pub theme: String,
+    pub volume: f32,
+    pub orientation: PieceColor,
+    pub show_legal_moves: bool,
+    pub show_coordinates: bool,
+    pub animation_speed: f32,
+    pub time_control: Option<String>,
+    pub engine_path: Option<PathBuf>,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default().name,
+            volume: SoundSettings::default().volume,
+            orientation: PieceColor::White,
+            show_legal_moves: true,
+            show_coordinates: true,
+            animation_speed: 1.0,
+            time_control: None,
+            engine_path: None,
+        }
+    }
+}
+
+impl UserSettings {
+    /// Where the settings are kept: `$XDG_CONFIG_HOME` or `~/.config` on Linux,
+    /// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows.
+    pub fn path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+        dir.map(|dir| dir.join("chess").join("settings.conf"))
+    }
+
+    /// The saved settings. A missing file gives the defaults.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Reads a settings file. Anything it leaves out keeps its default. A line that can't be read
+    /// is skipped with a warning, so the rest of the file still counts.
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(err) = settings.set(line) {
+                warn!("Skipping line {} of the settings: {err}", number + 1);
+            }
+        }
+        settings
+    }
+
+    // Takes a `key = value` line.
+    fn set(&mut self, line: &str) -> Result<(), String> {
+        let Some((key, value)) = line.split_once('=') else {
+            return Err("expected `key = value`".to_string());
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        let invalid = || format!("invalid {key} {value:?}");
+        let flag = || match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(invalid()),
+        };
+        let float = || value.parse::<f32>().map_err(|_| invalid());
+        match key {
+            "theme" => self.theme = value.to_string(),
+            "volume" => self.volume = float()?.clamp(0.0, 1.0),
+            "orientation" => {
+                self.orientation = match value {
+                    "white" => PieceColor::White,
+                    "black" => PieceColor::Black,
+                    _ => return Err(invalid()),
+                }
+            }
+            "show_legal_moves" => self.show_legal_moves = flag()?,
+            "show_coordinates" => self.show_coordinates = flag()?,
+            "animation_speed" => self.animation_speed = float()?.max(0.0),
+            "time_control" => self.time_control = (value != "none").then(|| value.to_string()),
+            "engine" => self.engine_path = (!value.is_empty()).then(|| value.into()),
+            _ => return Err(format!("unknown key {key:?}")),
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let orientation = match self.orientation {
+            PieceColor::White => "white",
+            PieceColor::Black => "black",
+        };
+        let engine = self
+            .engine_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        format!(
+            "theme = {}\nvolume = {}\norientation = {orientation}\nshow_legal_moves = {}\n\
+             show_coordinates = {}\nanimation_speed = {}\ntime_control = {}\nengine = {engine}\n",
+            self.theme,
+            self.volume,
+            self.show_legal_moves,
+            self.show_coordinates,
+            self.animation_speed,
+            self.time_control.as_deref().unwrap_or("none"),
+        )
+    }
+
+    fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+        let saved = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.to_text()));
+        if let Err(err) = saved {
+            warn!("Could not save settings to {}: {err}", path.display());
+        }
+    }
+}
+
+// Whether the engine path is being typed in.
+#[derive(Resource, Default)]
+struct EnginePathInput {
+    editing: bool,
+}
+
+// The choices show their current option as their label, see `update_settings_text_system`.
+fn spawn_settings_screen(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(16.0),
+            ..default()
+        },
+        DespawnOnExit(AppState::Settings),
+        children![
+            (
+                Text::new("Settings"),
+                TextFont {
+                    font_size: 64.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ),
+            text_button("", SettingsAction::Theme),
+            text_button("", SettingsAction::Volume),
+            text_button("", SettingsAction::Orientation),
+            text_button("", SettingsAction::LegalMoves),
+            text_button("", SettingsAction::Coordinates),
+            text_button("", SettingsAction::AnimationSpeed),
+            text_button("", SettingsAction::TimeControl),
+            text_button("", SettingsAction::EnginePath),
+            text_button("Back", SettingsAction::Back),
+        ],
+    ));
+}
+
+// Each choice takes effect right away, except the engine, which is started with the app.
+#[allow(clippy::too_many_arguments)]
+fn settings_buttons_system(
+    button_query: Query<(&Interaction, &SettingsAction), Changed<Interaction>>,
+    mut settings: ResMut<UserSettings>,
+    mut input: ResMut<EnginePathInput>,
+    themes: Res<Themes>,
+    mut theme: ResMut<Theme>,
+    mut sound: ResMut<SoundSettings>,
+    mut display_options: ResMut<DisplayOptions>,
+    mut setup: ResMut<GameSetup>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, action) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if *action != SettingsAction::EnginePath {
+            input.editing = false;
+        }
+
+        match action {
+            SettingsAction::Theme => {
+                let current = themes
+                    .0
+                    .iter()
+                    .position(|other| other.name == theme.name)
+                    .unwrap_or(0);
+                *theme = themes.0[(current + 1) % themes.0.len()].clone();
+                settings.theme = theme.name.clone();
+            }
+            // Steps of a tenth, back to silence after full volume.
+            SettingsAction::Volume => {
+                let step = (settings.volume * 10.0).round() as u32;
+                settings.volume = ((step + 1) % 11) as f32 / 10.0;
+                sound.volume = settings.volume;
+            }
+            SettingsAction::Orientation => {
+                settings.orientation = match settings.orientation {
+                    PieceColor::White => PieceColor::Black,
+                    PieceColor::Black => PieceColor::White,
+                };
+                setup.color = Some(settings.orientation);
+            }
+            SettingsAction::LegalMoves => {
+                settings.show_legal_moves = !settings.show_legal_moves;
+                display_options.show_legal_moves = settings.show_legal_moves;
+            }
+            SettingsAction::Coordinates => {
+                settings.show_coordinates = !settings.show_coordinates;
+                display_options.show_coordinates = settings.show_coordinates;
+            }
+            SettingsAction::AnimationSpeed => {
+                let current = ANIMATION_SPEEDS
+                    .iter()
+                    .position(|(speed, _)| *speed == settings.animation_speed)
+                    .unwrap_or(1);
+                settings.animation_speed =
+                    ANIMATION_SPEEDS[(current + 1) % ANIMATION_SPEEDS.len()].0;
+                display_options.animation_speed = settings.animation_speed;
+            }
+            SettingsAction::TimeControl => {
+                let mut options: Vec<Option<String>> = vec![None];
+                options.extend(TIME_CONTROLS.iter().map(|spec| Some(spec.to_string())));
+                let current = options
+                    .iter()
+                    .position(|option| *option == settings.time_control)
+                    .unwrap_or(0);
+                settings.time_control = options[(current + 1) % options.len()].clone();
+                setup.time_control = settings.time_control.clone();
+            }
+            SettingsAction::EnginePath => input.editing = !input.editing,
+            SettingsAction::Back => next_state.set(AppState::MainMenu),
+        }
+    }
+}
+
+// Typing goes into the engine path while it is being edited. Enter or Escape ends the editing.
+fn engine_path_input_system(
+    mut keyboard_events: MessageReader<KeyboardInput>,
+    mut input: ResMut<EnginePathInput>,
+    mut settings: ResMut<UserSettings>,
+) {
+    for event in keyboard_events.read() {
+        if !input.editing || !event.state.is_pressed() {
+            continue;
+        }
+
+        let mut path = settings
+            .engine_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        match &event.logical_key {
+            Key::Enter | Key::Escape => {
+                input.editing = false;
+                continue;
+            }
+            Key::Backspace => {
+                path.pop();
+            }
+            Key::Space => path.push(' '),
+            Key::Character(text) => path.push_str(text),
+            _ => continue,
+        }
+        settings.engine_path = (!path.is_empty()).then(|| path.into());
+    }
+}
+
+fn stop_editing(mut input: ResMut<EnginePathInput>) {
+    input.editing = false;
+}
+
+fn update_settings_text_system(
+    settings: Res<UserSettings>,
+    input: Res<EnginePathInput>,
+    button_query: Query<(&SettingsAction, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    let on_off = |on: bool| if on { "On" } else { "Off" };
+
+    for (action, children) in button_query.iter() {
+        let label = match action {
+            SettingsAction::Theme => format!("Theme: {}", settings.theme),
+            SettingsAction::Volume => format!("Volume: {:.0}%", settings.volume * 100.0),
+            SettingsAction::Orientation => format!("Bottom side: {}", settings.orientation),
+            SettingsAction::LegalMoves => {
+                format!("Legal moves: {}", on_off(settings.show_legal_moves))
+            }
+            SettingsAction::Coordinates => {
+                format!("Coordinates: {}", on_off(settings.show_coordinates))
+            }
+            SettingsAction::AnimationSpeed => {
+                let name = ANIMATION_SPEEDS
+                    .iter()
+                    .find(|(speed, _)| *speed == settings.animation_speed)
+                    .map_or("Custom", |(_, name)| name);
+                format!("Animations: {name}")
+            }
+            SettingsAction::TimeControl => format!(
+                "Time control: {}",
+                settings.time_control.as_deref().unwrap_or("Untimed")
+            ),
+            // A new engine is only started with the app.
+            SettingsAction::EnginePath => {
+                let path = settings
+                    .engine_path
+                    .as_ref()
+                    .map_or("None".to_string(), |path| path.display().to_string());
+                if input.editing {
+                    format!("Engine: {path}_")
+                } else {
+                    format!("Engine: {path} (on next start)")
+                }
+            }
+            SettingsAction::Back => continue,
+        };
+
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child)
+                && text.0 != label
+            {
+                text.0 = label.clone();
+            }
+        }
+    }
+}
+
+// Changes made with the keys in a game (theme, volume, coordinates) are kept as well.
+fn remember_settings_system(
+    mut settings: ResMut<UserSettings>,
+    theme: Res<Theme>,
+    sound: Res<SoundSettings>,
+    display_options: Res<DisplayOptions>,
+) {
+    if theme.is_changed() && !theme.is_added() && settings.theme != theme.name {
+        settings.theme = theme.name.clone();
+    }
+    if sound.is_changed() && !sound.is_added() && settings.volume != sound.volume {
+        settings.volume = sound.volume;
+    }
+    if display_options.is_changed()
+        && !display_options.is_added()
+        && settings.show_coordinates != display_options.show_coordinates
+    {
+        settings.show_coordinates = display_options.show_coordinates;
+    }
+}
+
+fn save_settings_system(settings: Res<UserSettings>) {
+    if settings.is_changed() && !settings.is_added() {
+        settings.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let settings = UserSettings {
+            theme: "Ocean".to_string(),
+            volume: 0.3,
+            orientation: PieceColor::Black,
+            show_legal_moves: false,
+            show_coordinates: true,
+            animation_speed: 2.0,
+            time_control: Some("5+3".to_string()),
+            engine_path: Some("/usr/games/stockfish".into()),
+        };
+        assert_eq!(UserSettings::parse(&settings.to_text()), settings);
+        assert_eq!(
+            UserSettings::parse(&UserSettings::default().to_text()),
+            UserSettings::default()
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_skipped_on_their_own() {
+        let text = "# Saved by an older version
+                    theme = Walnut
+                    board_size = large
+                    volume = loud
+                    show_coordinates = false
+                    orientation = sideways
+                    just some words
+                    time_control = none
+                    animation_speed = -1
+";
+        let settings = UserSettings::parse(text);
+        assert_eq!(settings.theme, "Walnut");
+        assert!(!settings.show_coordinates);
+        assert_eq!(settings.volume, UserSettings::default().volume);
+        assert_eq!(settings.orientation, PieceColor::White);
+        assert_eq!(settings.time_control, None);
+        assert_eq!(settings.animation_speed, 0.0);
+    }
+}
@@ -0,0 +1,153 @@
+// Sound effects for moves, low time and the end of the game.
+
+use bevy::{audio::Volume, prelude::*};
+
+use crate::{
+    clock::Clocks,
+    components::PieceColor,
+    resources::{GameState, MoveHistory, SoundSettings},
+    states::{AppState, InGame},
+};
+
+const VOLUME_STEP: f32 = 0.1;
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_sounds)
+            // The keys type into the engine path on the settings screen.
+            .add_systems(
+                Update,
+                sound_keys_system.run_if(not(in_state(AppState::Settings))),
+            )
+            .add_systems(
+                Update,
+                (
+                    move_sound_system,
+                    low_time_sound_system,
+                    game_end_sound_system,
+                )
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct Sounds {
+    quiet: Handle<AudioSource>,
+    capture: Handle<AudioSource>,
+    castle: Handle<AudioSource>,
+    check: Handle<AudioSource>,
+    promote: Handle<AudioSource>,
+    low_time: Handle<AudioSource>,
+    game_end: Handle<AudioSource>,
+}
+
+fn load_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Sounds {
+        quiet: asset_server.load("sounds/move.wav"),
+        capture: asset_server.load("sounds/capture.wav"),
+        castle: asset_server.load("sounds/castle.wav"),
+        check: asset_server.load("sounds/check.wav"),
+        promote: asset_server.load("sounds/promote.wav"),
+        low_time: asset_server.load("sounds/low-time.wav"),
+        game_end: asset_server.load("sounds/game-end.wav"),
+    });
+}
+
+fn play(commands: &mut Commands, sound: &Handle<AudioSource>, settings: &SoundSettings) {
+    if settings.muted || settings.volume <= 0.0 {
+        return;
+    }
+    commands.spawn((
+        AudioPlayer::new(sound.clone()),
+        PlaybackSettings::DESPAWN.with_volume(Volume::Linear(settings.volume)),
+    ));
+}
+
+// M mutes and unmutes, - and = turn the volume down and up.
+fn sound_keys_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<SoundSettings>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyM) {
+        settings.muted = !settings.muted;
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        settings.volume = (settings.volume - VOLUME_STEP).max(0.0);
+    }
+    if keyboard_input.just_pressed(KeyCode::Equal) {
+        settings.volume = (settings.volume + VOLUME_STEP).min(1.0);
+    }
+}
+
+// One sound per move, picked from its SAN: a check outranks a promotion, which outranks castling
+// and captures. Redoing a move plays it again, undoing one is silent.
+fn move_sound_system(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    sounds: Res<Sounds>,
+    settings: Res<SoundSettings>,
+    mut seen: Local<usize>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+    let grew = history.moves.len() > *seen;
+    *seen = history.moves.len();
+    let Some(record) = history.moves.last().filter(|_| grew) else {
+        return;
+    };
+
+    let san = record.san.as_str();
+    let sound = if san.ends_with(['+', '#']) {
+        &sounds.check
+    } else if san.contains('=') {
+        &sounds.promote
+    } else if san.starts_with("O-O") {
+        &sounds.castle
+    } else if san.contains('x') {
+        &sounds.capture
+    } else {
+        &sounds.quiet
+    };
+    play(&mut commands, sound, &settings);
+}
+
+// Once for each clock when it drops below the low time mark.
+fn low_time_sound_system(
+    mut commands: Commands,
+    clocks: Option<Res<Clocks>>,
+    sounds: Res<Sounds>,
+    settings: Res<SoundSettings>,
+    mut was_low: Local<[bool; 2]>,
+) {
+    let Some(clocks) = clocks else {
+        return;
+    };
+    for (i, color) in [PieceColor::White, PieceColor::Black]
+        .into_iter()
+        .enumerate()
+    {
+        let low = clocks.get(color).is_low();
+        if low && !was_low[i] && !clocks.is_added() {
+            play(&mut commands, &sounds.low_time, &settings);
+        }
+        was_low[i] = low;
+    }
+}
+
+fn game_end_sound_system(
+    mut commands: Commands,
+    game_state: Res<GameState>,
+    sounds: Res<Sounds>,
+    settings: Res<SoundSettings>,
+    mut was_over: Local<bool>,
+) {
+    let over = game_state.result.is_some();
+    if over && !*was_over {
+        play(&mut commands, &sounds.game_end, &settings);
+    }
+    *was_over = over;
+}
@@ -0,0 +1,25 @@
+use bevy::prelude::*;
+
+#[derive(States, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AppState {
+    #[default]
+    MainMenu,
+    // Choosing the opponent, colors, time control and start position.
+    GameSetup,
+    Settings,
+    Playing,
+    // The game has ended, but its board stays up behind the result.
+    GameOver,
+}
+
+// A game is on the board, running or over. Game entities live exactly as long as this state.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InGame;
+
+impl ComputedStates for InGame {
+    type SourceStates = AppState;
+
+    fn compute(state: AppState) -> Option<Self> {
+        matches!(state, AppState::Playing | AppState::GameOver).then_some(InGame)
+    }
+}
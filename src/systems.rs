@@ -1,12 +1,22 @@
 use crate::{
-    board::{OFFSET, TILE_SIZE, get_world_position},
-    chess::{get_legal_moves, is_king_in_check, is_legal_move},
+    board::{TILE_SIZE, get_board_square, get_world_position, piece_bundle, piece_image_path},
+    chess::{
+        Position, en_passant_capture_square, get_legal_moves, is_king_in_check, is_legal_move,
+        is_promotion, make_move, to_san,
+    },
     components::{
-        InCheckHighlight, LegalMovesFilter, MovedFilter, Piece, PieceColor, PieceKind, Selected,
+        CaptureAnimation, Dragging, HistoryHighlight, HistoryPiece, InCheckHighlight,
+        LegalMovesFilter, MoveAnimation, MovedFilter, Piece, PieceColor, PieceKind, Selected,
         SelectedFilter, Square,
     },
-    events::MoveMadeEvent,
-    resources::GameState,
+    events::{MoveMadeEvent, MoveRequestedEvent, NewGameEvent},
+    premove::Premoves,
+    resources::{
+        Annotations, BoardOrientation, DisplayOptions, GameSetup, GameState, LegalMoves,
+        MoveHistory, MoveRecord, PlayerKind, Players,
+    },
+    states::{AppState, InGame},
+    theme::Theme,
 };
 use bevy::{prelude::*, window::PrimaryWindow};
 
@@ -14,53 +24,85 @@ pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                input_system,
-                highlight_selected_piece_system.after(input_system),
-                highlight_legal_moves_system,
-                piece_movement_system,
-            ),
-        )
-        .add_observer(on_move_made);
+        app.init_resource::<LegalMoves>()
+            .add_systems(
+                Update,
+                (
+                    input_system,
+                    drag_system.after(input_system),
+                    drop_system.after(drag_system),
+                    highlight_selected_piece_system.after(input_system),
+                    highlight_legal_moves_system,
+                )
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(OnExit(InGame), clear_board_system)
+            .add_observer(on_move_requested)
+            .add_observer(on_move_made)
+            .add_observer(on_new_game);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn input_system(
     mut commands: Commands,
     mouse_input: Res<ButtonInput<MouseButton>>,
     window_query: Query<&Window, With<PrimaryWindow>>,
     camera_query: Query<(&Camera, &GlobalTransform)>,
-    mut piece_query: Query<(Entity, &mut Piece, &mut Square)>,
+    piece_query: Query<(Entity, &Piece, &Square)>,
     selected_piece_query: Query<Entity, With<Selected>>,
-    mut game_state: ResMut<GameState>,
+    game_state: Res<GameState>,
+    players: Res<Players>,
+    orientation: Res<BoardOrientation>,
+    history: Res<MoveHistory>,
+    mut premoves: ResMut<Premoves>,
 ) {
     if !mouse_input.just_pressed(MouseButton::Left) {
         return;
     }
 
-    let Ok(window) = window_query.single() else {
+    // Past positions can only be looked at, finished games take no more moves and spectators only
+    // watch.
+    if history.is_browsing() || game_state.result.is_some() || players.is_observing() {
         return;
-    };
-    let Ok((camera, camera_transform)) = camera_query.single() else {
-        return;
-    };
+    }
 
-    let Some(world_position) = window
-        .cursor_position()
-        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor).ok())
+    let Some((x, y)) = cursor_world_position(&window_query, &camera_query)
+        .and_then(|world_position| get_board_square(world_position, &orientation))
     else {
         return;
     };
 
-    let x = ((world_position.x + OFFSET) / TILE_SIZE).floor() as u8;
-    let y = ((world_position.y + OFFSET) / TILE_SIZE).floor() as u8;
-
-    if !(0..8).contains(&x) || !(0..8).contains(&y) {
-        return;
+    let opponent = match game_state.turn {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    if players.is_human(game_state.turn) {
+        activate_square(
+            &mut commands,
+            (x, y),
+            &piece_query,
+            &selected_piece_query,
+            &game_state,
+            true,
+        );
+    } else if players.is_human(opponent) {
+        // While the engine thinks, the human queues premoves.
+        let board: Vec<(Piece, Square)> = piece_query.iter().map(|(_, p, s)| (*p, *s)).collect();
+        premoves.click((x, y), &board, opponent);
     }
+}
 
+// What a click, or Enter on the keyboard cursor, does on a square: select a piece of the side to
+// move, or play the selected piece there. With the mouse the piece is also picked up for dragging.
+pub fn activate_square(
+    commands: &mut Commands,
+    (x, y): (u8, u8),
+    piece_query: &Query<(Entity, &Piece, &Square)>,
+    selected_piece_query: &Query<Entity, With<Selected>>,
+    game_state: &GameState,
+    drag: bool,
+) {
     // Finding the piece we just clicked (if any).
     /*
         If clicked_piece = None, the player clicked a square with no piece on it.
@@ -82,9 +124,6 @@ fn input_system(
         }
     }
 
-    // Saving the board.
-    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(_, p, s)| (*p, *s)).collect();
-
     // Decision Tree + Execution.
     match (selected_piece, clicked_piece) {
         // Case 1: Select a Piece
@@ -92,7 +131,7 @@ fn input_system(
             if piece.color != game_state.turn {
                 return;
             }
-            commands.entity(entity).insert(Selected);
+            select(commands, entity, drag);
         }
 
         // Case 2: Selected piece wants to move to Empty Square (Includes Castling)
@@ -101,91 +140,13 @@ fn input_system(
                 return;
             }
 
-            // Identify if we need to move a Rook, BEFORE we borrow the query mutably.
-            let mut castling_rook_task: Option<(Entity, (u8, u8))> = None;
-
-            if selected_piece_data.kind == PieceKind::King {
-                if let Ok((_, _, sq)) = piece_query.get(entity) {
-                    let dx = x as i8 - sq.x as i8;
-
-                    // If King moved 2 squares, it's a Castle attempt
-                    if dx.abs() == 2 {
-                        let rook_start_pos = match (x, y) {
-                            (6, 0) => Some((7, 0)), // White King Side
-                            (2, 0) => Some((0, 0)), // White Queen Side
-                            (6, 7) => Some((7, 7)), // Black King Side
-                            (2, 7) => Some((0, 7)), // Black Queen Side
-                            _ => None,
-                        };
-
-                        if let Some(r_pos) = rook_start_pos {
-                            let rook_dest = match r_pos {
-                                (7, 0) => (5, 0),
-                                (0, 0) => (3, 0),
-                                (7, 7) => (5, 7),
-                                (0, 7) => (3, 7),
-                                _ => (0, 0), // Should not happen
-                            };
-
-                            // Search for the Rook Entity
-                            if let Some((r_entity, _, _)) = piece_query
-                                .iter()
-                                .find(|(_, _, s)| s.x == r_pos.0 && s.y == r_pos.1)
-                            {
-                                castling_rook_task = Some((r_entity, rook_dest));
-                            }
-                        }
-                    }
-                }
-            }
-
-            if let Ok((_, mut piece, mut square)) = piece_query.get_mut(entity) {
-                let (prev_x, prev_y) = (square.x, square.y);
-
-                if !is_legal_move(&selected_piece_data, (prev_x, prev_y), (x, y), &board) {
-                    return;
-                }
-
-                // Move.
-                square.x = x;
-                square.y = y;
-
-                // Update has_moved flag.
-                piece.has_moved = true;
-
-                // Event.
-                commands.trigger(MoveMadeEvent {
-                    piece: entity,
-                    start: (prev_x, prev_y),
+            if let Ok((_, _, square)) = piece_query.get(entity) {
+                commands.trigger(MoveRequestedEvent {
+                    start: (square.x, square.y),
                     end: (x, y),
+                    promotion: None,
                 });
             }
-
-            // Only runs if Phase A found a valid castling task
-            if let Some((rook_entity, rook_dest)) = castling_rook_task {
-                if let Ok((_, mut rook_piece, mut rook_square)) = piece_query.get_mut(rook_entity) {
-                    if !rook_piece.has_moved {
-                        let (r_prev_x, r_prev_y) = (rook_square.x, rook_square.y);
-                        // Move Rook.
-                        rook_square.x = rook_dest.0;
-                        rook_square.y = rook_dest.1;
-
-                        rook_piece.has_moved = true;
-
-                        commands.trigger(MoveMadeEvent {
-                            piece: rook_entity,
-                            start: (r_prev_x, r_prev_y),
-                            end: rook_dest,
-                        });
-                    }
-                }
-            }
-
-            game_state.turn = match game_state.turn {
-                PieceColor::White => PieceColor::Black,
-                PieceColor::Black => PieceColor::White,
-            };
-            commands.entity(entity).remove::<Selected>();
         }
 
         // Case 3: Selected piece wants to move to a square that already has a piece on it => 3 sub-cases.
@@ -194,58 +155,365 @@ fn input_system(
                 return;
             }
 
-            // Sub-case 1: Clicked same piece -> Deselect
+            // Sub-case 1: Clicked same piece -> Deselect (once released without moving it)
             if curr_entity == target_entity {
-                commands.entity(curr_entity).remove::<Selected>();
+                if drag {
+                    commands
+                        .entity(curr_entity)
+                        .insert(Dragging { was_selected: true });
+                } else {
+                    commands.entity(curr_entity).remove::<Selected>();
+                }
             }
             // Sub-case 2: Clicked Friend -> Switch Selection
             else if curr_piece.color == target_piece.color {
                 commands.entity(curr_entity).remove::<Selected>();
-                commands.entity(target_entity).insert(Selected);
+                select(commands, target_entity, drag);
             }
             // Sub-case 3: Clicked Enemy -> CAPTURE
-            else {
-                // 1. Validate
-                if let Ok((_, _, square)) = piece_query.get(curr_entity) {
-                    if !is_legal_move(&curr_piece, (square.x, square.y), (x, y), &board) {
-                        return;
-                    }
-                }
+            else if let Ok((_, _, square)) = piece_query.get(curr_entity) {
+                commands.trigger(MoveRequestedEvent {
+                    start: (square.x, square.y),
+                    end: (x, y),
+                    promotion: None,
+                });
+            }
+        }
+        // TODO Case 4: Something is selected or not it doesn't matter -> Player clicked somewhere outside the board => Maybe clicked the UI, check.
+        _ => {}
+    }
+}
+
+fn select(commands: &mut Commands, entity: Entity, drag: bool) {
+    if drag {
+        commands.entity(entity).insert((
+            Selected,
+            Dragging {
+                was_selected: false,
+            },
+        ));
+    } else {
+        commands.entity(entity).insert(Selected);
+    }
+}
+
+// Converts the cursor position into world coordinates.
+pub fn cursor_world_position(
+    window_query: &Query<&Window, With<PrimaryWindow>>,
+    camera_query: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Vec2> {
+    let window = window_query.single().ok()?;
+    let (camera, camera_transform) = camera_query.single().ok()?;
+
+    window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor).ok())
+}
 
-                // 2. Execute Capture
-                commands.entity(target_entity).despawn();
+// The dragged piece follows the cursor, drawn above every other piece.
+fn drag_system(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut dragging_query: Query<&mut Transform, With<Dragging>>,
+) {
+    if !mouse_input.pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(world_position) = cursor_world_position(&window_query, &camera_query) else {
+        return;
+    };
+
+    for mut transform in dragging_query.iter_mut() {
+        transform.translation = world_position.extend(10.0);
+    }
+}
 
-                if let Ok((_, mut piece, mut square)) = piece_query.get_mut(curr_entity) {
-                    let (prev_x, prev_y) = (square.x, square.y);
-                    square.x = x;
-                    square.y = y;
+// Dropping a piece on a legal square plays the move, anywhere else it snaps back.
+#[allow(clippy::too_many_arguments)]
+fn drop_system(
+    mut commands: Commands,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut dragging_query: Query<(Entity, &Piece, &Square, &Dragging, &mut Transform)>,
+    piece_query: Query<(&Piece, &Square)>,
+    game_state: Res<GameState>,
+    orientation: Res<BoardOrientation>,
+) {
+    if !mouse_input.just_released(MouseButton::Left) {
+        return;
+    }
+    let target = cursor_world_position(&window_query, &camera_query)
+        .and_then(|world_position| get_board_square(world_position, &orientation));
 
-                    piece.has_moved = true;
+    for (entity, piece, square, dragging, mut transform) in dragging_query.iter_mut() {
+        commands
+            .entity(entity)
+            .remove::<(Dragging, MoveAnimation)>();
 
-                    game_state.turn = match game_state.turn {
-                        PieceColor::White => PieceColor::Black,
-                        PieceColor::Black => PieceColor::White,
-                    };
+        let start = (square.x, square.y);
+        let mut resting_square = start;
 
-                    commands.trigger(MoveMadeEvent {
-                        piece: curr_entity,
-                        start: (prev_x, prev_y),
-                        end: (x, y),
+        match target {
+            // Released where it was picked up, so it was a click rather than a drag.
+            Some(end) if end == start && dragging.was_selected => {
+                commands.entity(entity).remove::<Selected>();
+            }
+            Some(end) => {
+                let board: Vec<(Piece, Square)> =
+                    piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+                if is_legal_move(piece, start, end, &board, game_state.en_passant_target) {
+                    commands.trigger(MoveRequestedEvent {
+                        start,
+                        end,
+                        promotion: None,
                     });
+                    resting_square = end;
                 }
-                commands.entity(curr_entity).remove::<Selected>();
             }
+            None => {}
         }
-        // TODO Case 4: Something is selected or not it doesn't matter -> Player clicked somewhere outside the board => Maybe clicked the UI, check.
-        _ => {}
+
+        transform.translation = get_world_position(
+            resting_square.0 as usize,
+            resting_square.1 as usize,
+            1.0,
+            &orientation,
+        );
     }
 }
 
+fn on_move_requested(
+    event: On<MoveRequestedEvent>,
+    mut commands: Commands,
+    mut piece_query: Query<(Entity, &mut Piece, &mut Square)>,
+    mut game_state: ResMut<GameState>,
+    mut history: ResMut<MoveHistory>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+) {
+    let (start, end) = (event.start, event.end);
+
+    let Some((entity, moving_piece)) = piece_query
+        .iter()
+        .find(|(_, _, s)| s.x == start.0 && s.y == start.1)
+        .map(|(e, p, _)| (e, *p))
+    else {
+        return;
+    };
+    if moving_piece.color != game_state.turn || game_state.result.is_some() {
+        return;
+    }
+
+    // Saving the board.
+    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(_, p, s)| (*p, *s)).collect();
+    if !is_legal_move(
+        &moving_piece,
+        start,
+        end,
+        &board,
+        game_state.en_passant_target,
+    ) {
+        return;
+    }
+
+    let promotion =
+        is_promotion(&moving_piece, end).then(|| event.promotion.unwrap_or(PieceKind::Queen));
+    let san = to_san(&board, start, end, promotion, game_state.en_passant_target);
+    let before = Position {
+        board: board.clone(),
+        turn: game_state.turn,
+        en_passant: game_state.en_passant_target,
+        halfmove_clock: game_state.halfmove_clock,
+        fullmove_number: game_state.fullmove_number,
+    };
+
+    // The captured piece is on the target square, except for en passant.
+    let captured_square = en_passant_capture_square(
+        &moving_piece,
+        start,
+        end,
+        &board,
+        game_state.en_passant_target,
+    )
+    .unwrap_or(end);
+    let captured_entity = piece_query
+        .iter()
+        .find(|(_, _, s)| s.x == captured_square.0 && s.y == captured_square.1)
+        .map(|(e, _, _)| e);
+
+    // Identify if we need to move a Rook, BEFORE we borrow the query mutably.
+    let mut castling_rook_task: Option<(Entity, (u8, u8))> = None;
+    let dx = end.0 as i8 - start.0 as i8;
+    // If King moved 2 squares, it's a Castle
+    if moving_piece.kind == PieceKind::King && dx.abs() == 2 {
+        let (rook_start, rook_dest) = if dx > 0 {
+            ((7, end.1), (5, end.1)) // King Side
+        } else {
+            ((0, end.1), (3, end.1)) // Queen Side
+        };
+
+        // Search for the Rook Entity
+        if let Some((r_entity, _, _)) = piece_query
+            .iter()
+            .find(|(_, _, s)| s.x == rook_start.0 && s.y == rook_start.1)
+        {
+            castling_rook_task = Some((r_entity, rook_dest));
+        }
+    }
+
+    // Execute Capture. The captured piece leaves the board right away, but its sprite fades out.
+    if let Some(captured_entity) = captured_entity {
+        commands
+            .entity(captured_entity)
+            .remove::<(Piece, Square, Selected)>()
+            .insert(CaptureAnimation::default());
+    }
+
+    if let Ok((_, mut piece, mut square)) = piece_query.get_mut(entity) {
+        // Move.
+        square.x = end.0;
+        square.y = end.1;
+
+        // Update has_moved flag.
+        piece.has_moved = true;
+
+        // Promotion swaps the pawn for the chosen piece, including its sprite.
+        if let Some(kind) = promotion {
+            piece.kind = kind;
+            commands.entity(entity).insert(Sprite {
+                image: asset_server.load(piece_image_path(&theme, piece.color, piece.kind)),
+                ..Default::default()
+            });
+        }
+
+        // Event.
+        commands.trigger(MoveMadeEvent {
+            piece: entity,
+            start,
+            end,
+        });
+    }
+
+    // Only runs if a valid castling task was found
+    if let Some((rook_entity, rook_dest)) = castling_rook_task
+        && let Ok((_, mut rook_piece, mut rook_square)) = piece_query.get_mut(rook_entity)
+    {
+        let (r_prev_x, r_prev_y) = (rook_square.x, rook_square.y);
+        // Move Rook.
+        rook_square.x = rook_dest.0;
+        rook_square.y = rook_dest.1;
+
+        rook_piece.has_moved = true;
+
+        commands.trigger(MoveMadeEvent {
+            piece: rook_entity,
+            start: (r_prev_x, r_prev_y),
+            end: rook_dest,
+        });
+    }
+
+    // A double pawn push leaves the skipped square open to en passant for one move.
+    let mut board_after = board;
+    game_state.en_passant_target = make_move(
+        &mut board_after,
+        start,
+        end,
+        promotion,
+        game_state.en_passant_target,
+    );
+
+    if moving_piece.kind == PieceKind::Pawn || captured_entity.is_some() {
+        game_state.halfmove_clock = 0;
+    } else {
+        game_state.halfmove_clock += 1;
+    }
+    if game_state.turn == PieceColor::Black {
+        game_state.fullmove_number += 1;
+    }
+
+    game_state.turn = match game_state.turn {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White,
+    };
+    commands.entity(entity).remove::<Selected>();
+
+    if history.start.is_none() {
+        history.start = Some(before);
+    }
+    history.redo.clear();
+    history.moves.push(MoveRecord {
+        start,
+        end,
+        san,
+        position: Position {
+            board: board_after,
+            turn: game_state.turn,
+            en_passant: game_state.en_passant_target,
+            halfmove_clock: game_state.halfmove_clock,
+            fullmove_number: game_state.fullmove_number,
+        },
+        annotations: Annotations::default(),
+    });
+}
+
+// Shades a start or end square of the last move.
+pub fn last_move_highlight(
+    square: (u8, u8),
+    orientation: &BoardOrientation,
+    theme: &Theme,
+) -> impl Bundle {
+    (
+        Sprite {
+            color: theme.last_move,
+            custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
+            ..Default::default()
+        },
+        Transform::from_translation(get_world_position(
+            square.0 as usize,
+            square.1 as usize,
+            0.5,
+            orientation,
+        )),
+        Square {
+            x: square.0,
+            y: square.1,
+        },
+    )
+}
+
+// A glow under a king in check.
+pub fn check_highlight(
+    square: (u8, u8),
+    orientation: &BoardOrientation,
+    asset_server: &AssetServer,
+    theme: &Theme,
+) -> impl Bundle {
+    let center = get_world_position(square.0 as usize, square.1 as usize, 0.9, orientation);
+
+    (
+        Sprite {
+            custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
+            image: asset_server.load(&theme.check_glow),
+            color: theme.check,
+            ..default()
+        },
+        Transform::from_xyz(center.x, center.y - 5.0, center.z),
+        Square {
+            x: square.0,
+            y: square.1,
+        },
+    )
+}
+
 fn highlight_selected_piece_system(
     mut commands: Commands,
     just_selected_square_query: Query<&Square, Added<Selected>>,
     previously_selected_square_query: Query<Entity, With<SelectedFilter>>,
     any_selected_square_query: Query<&Selected>,
+    orientation: Res<BoardOrientation>,
+    theme: Res<Theme>,
 ) {
     // If the player just selected a square with a piece on it.
     if !just_selected_square_query.is_empty() {
@@ -257,7 +525,7 @@ fn highlight_selected_piece_system(
         if let Ok(square) = just_selected_square_query.single() {
             commands.spawn((
                 Sprite {
-                    color: Color::srgba(0.6, 0.1, 0.8, 0.5),
+                    color: theme.selected,
                     custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
                     ..Default::default()
                 },
@@ -265,7 +533,9 @@ fn highlight_selected_piece_system(
                     square.x as usize,
                     square.y as usize,
                     0.5,
+                    &orientation,
                 )),
+                *square,
                 SelectedFilter,
             ));
         }
@@ -278,31 +548,45 @@ fn highlight_selected_piece_system(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn highlight_legal_moves_system(
     mut commands: Commands,
     piece_query: Query<(Entity, &Piece, &Square)>,
-    just_selected_square_query: Query<(&Piece, &Square), Added<Selected>>,
+    just_selected_square_query: Query<(Entity, &Piece, &Square), Added<Selected>>,
     previously_highlighted_legal_moves_query: Query<Entity, With<LegalMovesFilter>>,
     any_selected_query: Query<&Selected>,
+    game_state: Res<GameState>,
+    orientation: Res<BoardOrientation>,
+    theme: Res<Theme>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    mut legal_moves: ResMut<LegalMoves>,
+    display_options: Res<DisplayOptions>,
 ) {
     // CASE 1: A new piece was just selected
-    if let Ok((piece, square)) = just_selected_square_query.single() {
+    if let Ok((entity, piece, square)) = just_selected_square_query.single() {
         for entity in previously_highlighted_legal_moves_query.iter() {
             commands.entity(entity).despawn();
         }
 
         let board: Vec<(Piece, Square)> = piece_query.iter().map(|(_, p, s)| (*p, *s)).collect();
         let start = (square.x, square.y);
-        let legal_moves = get_legal_moves(piece, start, &board);
-
-        let color = Color::srgba(0.6, 0.1, 0.8, 0.5);
-        for (x, y) in legal_moves {
+        *legal_moves = LegalMoves {
+            piece: Some(entity),
+            squares: get_legal_moves(piece, start, &board, game_state.en_passant_target),
+        };
+
+        let color = theme.legal_move;
+        let shown = if display_options.show_legal_moves {
+            legal_moves.squares.as_slice()
+        } else {
+            &[]
+        };
+        for &(x, y) in shown {
             if let Some((_, _, sq)) = piece_query.iter().find(|(_, _, sq)| sq.x == x && sq.y == y) {
                 commands.spawn((
                     Sprite {
-                        color: Color::srgba(0.6, 0.1, 0.8, 0.5),
+                        color,
                         custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
                         ..Default::default()
                     },
@@ -310,7 +594,9 @@ fn highlight_legal_moves_system(
                         sq.x as usize,
                         sq.y as usize,
                         0.5,
+                        &orientation,
                     )),
+                    *sq,
                     LegalMovesFilter,
                 ));
             }
@@ -319,7 +605,13 @@ fn highlight_legal_moves_system(
             commands.spawn((
                 Mesh2d(highlight_circle),
                 MeshMaterial2d(materials.add(color)),
-                Transform::from_translation(get_world_position(x as usize, y as usize, 0.5)),
+                Transform::from_translation(get_world_position(
+                    x as usize,
+                    y as usize,
+                    0.5,
+                    &orientation,
+                )),
+                Square { x, y },
                 LegalMovesFilter,
             ));
         }
@@ -331,8 +623,12 @@ fn highlight_legal_moves_system(
             commands.entity(entity).despawn();
         }
     }
+    if any_selected_query.is_empty() && legal_moves.piece.is_some() {
+        *legal_moves = LegalMoves::default();
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_move_made(
     event: On<MoveMadeEvent>,
     mut commands: Commands,
@@ -342,6 +638,8 @@ fn on_move_made(
     piece_query: Query<(Entity, &Piece, &Square)>,
     asset_server: Res<AssetServer>,
     game_state: Res<GameState>,
+    orientation: Res<BoardOrientation>,
+    theme: Res<Theme>,
 ) {
     // Remove the filter from the previous last move.
     for entity in previously_moved_piece_query.iter() {
@@ -355,31 +653,13 @@ fn on_move_made(
     if let Ok(_) = moved_piece_query.get(moved_entity) {
         // Lighter shade for the start square.
         commands.spawn((
-            Sprite {
-                color: Color::srgba(0.4, 0.89, 0.118, 0.61),
-                custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
-                ..Default::default()
-            },
-            Transform::from_translation(get_world_position(
-                previous_position.0 as usize,
-                previous_position.1 as usize,
-                0.5,
-            )),
+            last_move_highlight(previous_position, &orientation, &theme),
             MovedFilter,
         ));
 
         // Darker shade for the final square.
         commands.spawn((
-            Sprite {
-                color: Color::srgba(0.4, 0.89, 0.118, 0.61),
-                custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
-                ..Default::default()
-            },
-            Transform::from_translation(get_world_position(
-                new_position.0 as usize,
-                new_position.1 as usize,
-                0.5,
-            )),
+            last_move_highlight(new_position, &orientation, &theme),
             MovedFilter,
         ));
     }
@@ -388,16 +668,8 @@ fn on_move_made(
     for (_, piece, square) in piece_query.iter() {
         if piece.color == game_state.turn && piece.kind == PieceKind::King {
             if is_king_in_check((square.x, square.y), game_state.turn, &board) {
-                let center_x = (square.x as f32 * TILE_SIZE) - OFFSET + (TILE_SIZE / 2.0);
-                let center_y = (square.y as f32 * TILE_SIZE) - OFFSET + (TILE_SIZE / 2.0);
-
                 commands.spawn((
-                    Sprite {
-                        custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
-                        image: asset_server.load("effects/glow4.png"),
-                        ..default()
-                    },
-                    Transform::from_xyz(center_x, center_y - 5.0, 0.9),
+                    check_highlight((square.x, square.y), &orientation, &asset_server, &theme),
                     InCheckHighlight,
                 ));
             }
@@ -405,10 +677,114 @@ fn on_move_made(
     }
 }
 
-fn piece_movement_system(
-    mut movement_query: Query<(&Square, &mut Transform), (With<Piece>, Changed<Square>)>,
+// Pieces and highlights on the board, which come and go during a game.
+type BoardContents = Or<(
+    With<Piece>,
+    With<SelectedFilter>,
+    With<MovedFilter>,
+    With<LegalMovesFilter>,
+    With<InCheckHighlight>,
+    With<CaptureAnimation>,
+    With<HistoryPiece>,
+    With<HistoryHighlight>,
+)>;
+
+// Sets up the pieces for a new game. For a rematch the players change colors, and a lone human
+// keeps the board turned to their own side. A game loaded with `--pgn` is set up after its last
+// move, with all of its moves in the history.
+#[allow(clippy::too_many_arguments)]
+fn on_new_game(
+    event: On<NewGameEvent>,
+    mut commands: Commands,
+    board_query: Query<Entity, BoardContents>,
+    mut game_state: ResMut<GameState>,
+    mut players: ResMut<Players>,
+    mut orientation: ResMut<BoardOrientation>,
+    mut history: ResMut<MoveHistory>,
+    mut setup: ResMut<GameSetup>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
 ) {
-    for (square, mut transform) in movement_query.iter_mut() {
-        transform.translation = get_world_position(square.x as usize, square.y as usize, 1.0);
+    *history = MoveHistory::default();
+    for entity in board_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    if event.swap_colors {
+        let (white, black) = (players.white, players.black);
+        players.white = black;
+        players.black = white;
+
+        match (players.white, players.black) {
+            (PlayerKind::Human, PlayerKind::Engine | PlayerKind::Remote) => {
+                orientation.bottom = PieceColor::White
+            }
+            (PlayerKind::Engine | PlayerKind::Remote, PlayerKind::Human) => {
+                orientation.bottom = PieceColor::Black
+            }
+            _ => {}
+        }
+    }
+
+    // A loaded game is not sent over the network, so it is only played locally. A watched game
+    // comes with its moves so far in the same way.
+    let mut position = setup.start_position();
+    if !event.swap_colors
+        && (!players.has_remote() || players.is_observing())
+        && let Some(game) = setup.loaded_game.take()
+    {
+        let mut annotations = game.annotations.into_iter();
+        history.start_annotations = annotations.next().unwrap_or_default();
+        history.start = Some(game.start.clone());
+        position = game.start;
+        for (start, end, promotion) in game.moves {
+            let san = to_san(&position.board, start, end, promotion, position.en_passant);
+            position = position.play(start, end, promotion);
+            history.moves.push(MoveRecord {
+                start,
+                end,
+                san,
+                position: position.clone(),
+                annotations: annotations.next().unwrap_or_default(),
+            });
+        }
+
+        if let Some(record) = history.moves.last() {
+            for square in [record.start, record.end] {
+                commands.spawn((
+                    last_move_highlight(square, &orientation, &theme),
+                    MovedFilter,
+                ));
+            }
+        }
+        if let Some(king) = position.checked_king() {
+            commands.spawn((
+                check_highlight(king, &orientation, &asset_server, &theme),
+                InCheckHighlight,
+            ));
+        }
+    }
+
+    *game_state = GameState {
+        turn: position.turn,
+        en_passant_target: position.en_passant,
+        halfmove_clock: position.halfmove_clock,
+        fullmove_number: position.fullmove_number,
+        result: None,
+    };
+    for (piece, square) in position.board {
+        commands.spawn(piece_bundle(
+            piece,
+            square,
+            &asset_server,
+            &orientation,
+            &theme,
+        ));
+    }
+}
+
+fn clear_board_system(mut commands: Commands, board_query: Query<Entity, BoardContents>) {
+    for entity in board_query.iter() {
+        commands.entity(entity).despawn();
     }
 }
@@ -0,0 +1,306 @@
+// Board and piece themes. Besides the built-in one, every `*.theme` file in `assets/themes` is a
+// theme: lines of `key = value`, and comment lines starting with `#`. Colors are hex, like
+// `#e6e6cc` or `#66e31e9c` with alpha, and paths are relative to `assets`.
+//
+//   name          the name shown when switching to it (default: the file name)
+//   light_square  color of the light squares
+//   dark_square   color of the dark squares
+//   board_texture an image drawn over the whole board instead of the square colors
+//   pieces        directory with `{white,black}-{pawn,knight,bishop,rook,queen,king}.png`
+//   last_move     shade of the start and end square of the last move
+//   selected      shade of the square of the selected piece
+//   legal_move    color of the legal move dots and of the capturable squares
+//   check_glow    image of the glow under a king in check
+//   check         tint of that glow (default: white, which leaves it as it is)
+//   premove       shade of the squares of queued premoves
+//   focus         color of the keyboard cursor
+//   hover         shade of the square under the mouse
+
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    board::{SquareMaterials, board_texture, piece_image_path},
+    components::{
+        BoardTexture, InCheckHighlight, LegalMovesFilter, MovedFilter, Piece, SelectedFilter,
+    },
+    settings::UserSettings,
+    states::{AppState, InGame},
+};
+
+const THEME_DIR: &str = "assets/themes";
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        // The theme chosen in the settings, if it is still there.
+        let themes = Themes::load(Path::new(THEME_DIR));
+        let theme = match app.world().get_resource::<UserSettings>() {
+            Some(settings) => themes
+                .0
+                .iter()
+                .find(|theme| theme.name == settings.theme)
+                .cloned()
+                .unwrap_or_else(|| {
+                    warn!("Unknown theme {:?}", settings.theme);
+                    Theme::default()
+                }),
+            None => Theme::default(),
+        };
+
+        app.insert_resource(theme)
+            .insert_resource(themes)
+            .add_systems(
+                Update,
+                switch_theme_system.run_if(not(in_state(AppState::Settings))),
+            )
+            .add_systems(
+                Update,
+                apply_theme_system
+                    .after(switch_theme_system)
+                    .run_if(in_state(InGame)),
+            );
+    }
+}
+
+// The theme in use.
+#[derive(Resource, Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub light_square: Color,
+    pub dark_square: Color,
+    pub board_texture: Option<String>,
+    pub pieces: String,
+    pub last_move: Color,
+    pub selected: Color,
+    pub legal_move: Color,
+    pub check_glow: String,
+    pub check: Color,
+    pub premove: Color,
+    pub focus: Color,
+    pub hover: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "Classic".to_string(),
+            light_square: Color::srgb(0.9, 0.9, 0.8),
+            dark_square: Color::srgb(0.4, 0.4, 0.4),
+            board_texture: None,
+            pieces: "pieces".to_string(),
+            last_move: Color::srgba(0.4, 0.89, 0.118, 0.61),
+            selected: Color::srgba(0.6, 0.1, 0.8, 0.5),
+            legal_move: Color::srgba(0.6, 0.1, 0.8, 0.5),
+            check_glow: "effects/glow4.png".to_string(),
+            check: Color::WHITE,
+            premove: Color::srgba(0.2, 0.45, 0.85, 0.5),
+            focus: Color::srgb(0.25, 0.6, 1.0),
+            hover: Color::srgba(1.0, 1.0, 1.0, 0.2),
+        }
+    }
+}
+
+impl Theme {
+    /// Reads a theme file. Anything it leaves out is taken from the built-in theme.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut theme = Self {
+            name: name.to_string(),
+            ..default()
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected `key = value`", number + 1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            let color = || {
+                Srgba::hex(value)
+                    .map(Color::from)
+                    .map_err(|_| format!("line {}: invalid color {value:?}", number + 1))
+            };
+            match key {
+                "name" => theme.name = value.to_string(),
+                "light_square" => theme.light_square = color()?,
+                "dark_square" => theme.dark_square = color()?,
+                "board_texture" => theme.board_texture = Some(value.to_string()),
+                "pieces" => theme.pieces = value.trim_end_matches('/').to_string(),
+                "last_move" => theme.last_move = color()?,
+                "selected" => theme.selected = color()?,
+                "legal_move" => theme.legal_move = color()?,
+                "check_glow" => theme.check_glow = value.to_string(),
+                "check" => theme.check = color()?,
+                "premove" => theme.premove = color()?,
+                "focus" => theme.focus = color()?,
+                "hover" => theme.hover = color()?,
+                _ => return Err(format!("line {}: unknown key {key:?}", number + 1)),
+            }
+        }
+        Ok(theme)
+    }
+}
+
+// Every theme there is to switch between, the built-in one first.
+#[derive(Resource, Debug)]
+pub struct Themes(pub Vec<Theme>);
+
+impl Themes {
+    fn load(dir: &Path) -> Self {
+        let mut themes = vec![Theme::default()];
+
+        let mut paths: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "theme"))
+                .collect(),
+            Err(err) => {
+                warn!("Could not read themes from {}: {err}", dir.display());
+                Vec::new()
+            }
+        };
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| Theme::parse(&name, &text))
+            {
+                Ok(theme) => themes.push(theme),
+                Err(err) => warn!("Skipping theme {}: {err}", path.display()),
+            }
+        }
+        Self(themes)
+    }
+}
+
+fn switch_theme_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    themes: Res<Themes>,
+    mut theme: ResMut<Theme>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+
+    let current = themes
+        .0
+        .iter()
+        .position(|other| other.name == theme.name)
+        .unwrap_or(0);
+    *theme = themes.0[(current + 1) % themes.0.len()].clone();
+    info!("Theme: {}", theme.name);
+}
+
+// Repaints what is already on the board. The captured pieces, the coordinates and a past position
+// on display are redrawn by their own systems.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn apply_theme_system(
+    mut commands: Commands,
+    theme: Res<Theme>,
+    square_materials: Option<Res<SquareMaterials>>,
+    texture_query: Query<Entity, With<BoardTexture>>,
+    mut piece_query: Query<(&Piece, &mut Sprite)>,
+    mut highlight_query: Query<
+        (
+            &mut Sprite,
+            Has<MovedFilter>,
+            Has<SelectedFilter>,
+            Has<LegalMovesFilter>,
+            Has<InCheckHighlight>,
+        ),
+        Without<Piece>,
+    >,
+    dot_query: Query<&MeshMaterial2d<ColorMaterial>, With<LegalMovesFilter>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !theme.is_changed() || theme.is_added() {
+        return;
+    }
+
+    if let Some(square_materials) = square_materials {
+        if let Some(material) = materials.get_mut(&square_materials.light) {
+            material.color = theme.light_square;
+        }
+        if let Some(material) = materials.get_mut(&square_materials.dark) {
+            material.color = theme.dark_square;
+        }
+    }
+    for entity in texture_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    if let Some(path) = &theme.board_texture {
+        commands.spawn(board_texture(path, &asset_server));
+    }
+
+    for (piece, mut sprite) in piece_query.iter_mut() {
+        sprite.image = asset_server.load(piece_image_path(&theme, piece.color, piece.kind));
+    }
+    for (mut sprite, moved, selected, legal, check) in highlight_query.iter_mut() {
+        if moved {
+            sprite.color = theme.last_move;
+        } else if selected {
+            sprite.color = theme.selected;
+        } else if legal {
+            sprite.color = theme.legal_move;
+        } else if check {
+            sprite.image = asset_server.load(&theme.check_glow);
+            sprite.color = theme.check;
+        }
+    }
+    for material in dot_query.iter() {
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color = theme.legal_move;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_theme_files() {
+        let text = "# A comment\nname = Test\ndark_square = #112233\npieces = sets/test/\n";
+        let theme = Theme::parse("file", text).unwrap();
+        assert_eq!(theme.name, "Test");
+        assert_eq!(theme.dark_square, Color::from(Srgba::hex("112233").unwrap()));
+        assert_eq!(theme.pieces, "sets/test");
+        assert_eq!(theme.light_square, Theme::default().light_square);
+
+        assert!(Theme::parse("file", "dark_square = brown").is_err());
+        assert!(Theme::parse("file", "shade = #112233").is_err());
+        assert!(Theme::parse("file", "dark_square").is_err());
+    }
+
+    #[test]
+    fn the_included_themes_load() {
+        let themes = Themes::load(Path::new(THEME_DIR));
+        let names: Vec<_> = themes.0.iter().map(|theme| theme.name.as_str()).collect();
+        assert_eq!(names, ["Classic", "Ocean", "Walnut"]);
+        // Ocean has pieces and a glow color of its own.
+        assert_ne!(themes.0[1].pieces, themes.0[0].pieces);
+        assert_ne!(themes.0[1].check, Color::WHITE);
+    }
+
+    #[test]
+    fn the_hover_shade_is_translucent() {
+        let theme = Theme::parse("file", "hover = #fff3d933").unwrap();
+        assert_eq!(theme.hover, Color::srgba_u8(0xff, 0xf3, 0xd9, 0x33));
+        // Otherwise it would hide the square under the mouse.
+        let themes = Themes::load(Path::new(THEME_DIR));
+        assert!(themes.0.iter().all(|theme| theme.hover.alpha() < 0.5));
+    }
+}
@@ -0,0 +1,476 @@
+// Client for external engines speaking the Universal Chess Interface (UCI).
+// The engine runs as a child process; its output is read and parsed on a background thread.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver},
+    },
+    thread,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    chess::{is_legal_move, is_promotion, parse_uci_move, to_fen},
+    components::{Piece, PieceColor, Square},
+    events::{MoveRequestedEvent, NewGameEvent},
+    resources::{GameState, PlayerKind, Players},
+    settings::UserSettings,
+    states::AppState,
+};
+
+pub struct UciPlugin;
+
+impl Plugin for UciPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UciConfig>()
+            .add_systems(Startup, start_engine)
+            .add_observer(on_new_game)
+            .add_systems(
+                Update,
+                (
+                    read_engine_output,
+                    request_engine_move
+                        .after(read_engine_output)
+                        .run_if(in_state(AppState::Playing)),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct UciConfig {
+    // Executable of the engine. No engine is started without it.
+    pub path: Option<PathBuf>,
+    // The side the engine plays unless chosen otherwise on the setup screen. Without one, the engine
+    // is only used for analysis.
+    pub engine_color: Option<PieceColor>,
+    // Thinking time per move.
+    pub movetime_ms: u64,
+    // Sent as `setoption name <name> value <value>` before the first search.
+    pub options: Vec<(String, String)>,
+}
+
+impl Default for UciConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            engine_color: Some(PieceColor::Black),
+            movetime_ms: 1000,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl UciConfig {
+    /// Reads `--engine <path>`, `--engine-color <white|black|none>`, `--movetime <ms>` and
+    /// `--engine-option <name>=<value>` from the command line. Without `--engine`, the engine from
+    /// the settings is used.
+    pub fn from_args(args: impl IntoIterator<Item = String>, settings: &UserSettings) -> Self {
+        let mut config = Self {
+            path: settings.engine_path.clone(),
+            ..default()
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--engine" => config.path = args.next().map(PathBuf::from),
+                "--engine-color" => match args.next().as_deref() {
+                    Some("white") => config.engine_color = Some(PieceColor::White),
+                    Some("black") => config.engine_color = Some(PieceColor::Black),
+                    Some("none") => config.engine_color = None,
+                    other => warn!("Unknown engine color {other:?}"),
+                },
+                "--movetime" => {
+                    if let Some(ms) = args.next().and_then(|ms| ms.parse().ok()) {
+                        config.movetime_ms = ms;
+                    }
+                }
+                "--engine-option" => {
+                    if let Some((name, value)) =
+                        args.next().as_deref().and_then(|o| o.split_once('='))
+                    {
+                        config.options.push((name.to_string(), value.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Score {
+    Centipawns(i32),
+    // Moves until mate. Negative if the engine is getting mated.
+    Mate(i32),
+}
+
+// One `info` line. Every field is optional because engines only send what changed.
+#[derive(Clone, Default, Debug)]
+pub struct UciInfo {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time_ms: Option<u64>,
+    pub tbhits: Option<u64>,
+    pub pv: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum UciMessage {
+    Name(String),
+    UciOk,
+    ReadyOk,
+    Info(UciInfo),
+    BestMove(String),
+}
+
+pub fn parse_uci_line(line: &str) -> Option<UciMessage> {
+    let mut tokens = line.split_whitespace();
+    match tokens.next()? {
+        "id" => match tokens.next()? {
+            "name" => Some(UciMessage::Name(tokens.collect::<Vec<_>>().join(" "))),
+            _ => None,
+        },
+        "uciok" => Some(UciMessage::UciOk),
+        "readyok" => Some(UciMessage::ReadyOk),
+        "bestmove" => Some(UciMessage::BestMove(tokens.next()?.to_string())),
+        "info" => {
+            let mut info = UciInfo::default();
+            while let Some(key) = tokens.next() {
+                match key {
+                    "depth" => info.depth = tokens.next().and_then(|v| v.parse().ok()),
+                    "seldepth" => info.seldepth = tokens.next().and_then(|v| v.parse().ok()),
+                    "multipv" => info.multipv = tokens.next().and_then(|v| v.parse().ok()),
+                    "nodes" => info.nodes = tokens.next().and_then(|v| v.parse().ok()),
+                    "nps" => info.nps = tokens.next().and_then(|v| v.parse().ok()),
+                    "time" => info.time_ms = tokens.next().and_then(|v| v.parse().ok()),
+                    "tbhits" => info.tbhits = tokens.next().and_then(|v| v.parse().ok()),
+                    "score" => {
+                        let kind = tokens.next();
+                        let value = tokens.next().and_then(|v| v.parse().ok());
+                        info.score = match (kind, value) {
+                            (Some("cp"), Some(cp)) => Some(Score::Centipawns(cp)),
+                            (Some("mate"), Some(moves)) => Some(Score::Mate(moves)),
+                            _ => None,
+                        };
+                    }
+                    // The principal variation runs until the end of the line.
+                    "pv" => info.pv = tokens.by_ref().map(str::to_string).collect(),
+                    // Free text, nothing after it is a key.
+                    "string" => break,
+                    _ => {}
+                }
+            }
+            Some(UciMessage::Info(info))
+        }
+        _ => None,
+    }
+}
+
+pub struct UciProcess {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Mutex<Receiver<UciMessage>>,
+}
+
+impl UciProcess {
+    pub fn spawn(path: &Path) -> io::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("uci-reader".into())
+            .spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else { break };
+                    if let Some(message) = parse_uci_line(&line)
+                        && sender.send(message).is_err()
+                    {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            child,
+            stdin,
+            messages: Mutex::new(receiver),
+        })
+    }
+
+    pub fn send(&mut self, command: &str) {
+        if let Err(err) = writeln!(self.stdin, "{command}").and_then(|_| self.stdin.flush()) {
+            warn!("Could not send {command:?} to the engine: {err}");
+        }
+    }
+
+    /// Everything the engine said since the last call.
+    pub fn receive(&self) -> Vec<UciMessage> {
+        match self.messages.lock() {
+            Ok(receiver) => receiver.try_iter().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Drop for UciProcess {
+    fn drop(&mut self) {
+        self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SearchKind {
+    // The best move is played on the board.
+    Play,
+    // Runs until stopped, only the `info` output is used.
+    Analysis,
+}
+
+#[derive(Clone, Debug)]
+pub struct Search {
+    // The position (as FEN) the engine is thinking about.
+    pub fen: String,
+    pub kind: SearchKind,
+}
+
+#[derive(Resource)]
+pub struct UciEngine {
+    process: UciProcess,
+    pub name: Option<String>,
+    pub ready: bool,
+    pub searching: Option<Search>,
+    stopping: bool,
+    // Latest `info` about the main line of the current search.
+    pub last_info: Option<UciInfo>,
+    // The engine sent a move it may not play. Asking again would get the same answer, so it is
+    // not asked for moves any more.
+    pub disabled: bool,
+}
+
+impl UciEngine {
+    /// Starts searching a position, e.g. `go("...", SearchKind::Play, "movetime 1000")`.
+    pub fn go(&mut self, fen: String, kind: SearchKind, limits: &str) {
+        self.process.send(&format!("position fen {fen}"));
+        self.process.send(&format!("go {limits}"));
+        self.searching = Some(Search { fen, kind });
+        self.last_info = None;
+    }
+
+    /// Asks the engine to finish the current search. It is over once `bestmove` arrives.
+    pub fn stop(&mut self) {
+        if self.searching.is_some() && !self.stopping {
+            self.process.send("stop");
+            self.stopping = true;
+        }
+    }
+}
+
+// The result of a search from the last game is of no use in the next one.
+fn on_new_game(_event: On<NewGameEvent>, engine: Option<ResMut<UciEngine>>) {
+    if let Some(mut engine) = engine {
+        engine.stop();
+        engine.process.send("ucinewgame");
+    }
+}
+
+fn start_engine(mut commands: Commands, config: Res<UciConfig>) {
+    let Some(path) = &config.path else {
+        return;
+    };
+
+    let mut process = match UciProcess::spawn(path) {
+        Ok(process) => process,
+        Err(err) => {
+            error!("Could not start engine {}: {err}", path.display());
+            return;
+        }
+    };
+    process.send("uci");
+
+    commands.insert_resource(UciEngine {
+        process,
+        name: None,
+        ready: false,
+        searching: None,
+        stopping: false,
+        last_info: None,
+        disabled: false,
+    });
+}
+
+pub fn current_fen(piece_query: &Query<(&Piece, &Square)>, game_state: &GameState) -> String {
+    let board: Vec<(Piece, Square)> = piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+    to_fen(
+        &board,
+        game_state.turn,
+        game_state.en_passant_target,
+        game_state.halfmove_clock,
+        game_state.fullmove_number,
+    )
+}
+
+fn read_engine_output(
+    mut commands: Commands,
+    engine: Option<ResMut<UciEngine>>,
+    config: Res<UciConfig>,
+    piece_query: Query<(&Piece, &Square)>,
+    game_state: Res<GameState>,
+) {
+    let Some(mut engine) = engine else {
+        return;
+    };
+
+    for message in engine.process.receive() {
+        match message {
+            UciMessage::Name(name) => engine.name = Some(name),
+            UciMessage::UciOk => {
+                for (name, value) in &config.options {
+                    engine
+                        .process
+                        .send(&format!("setoption name {name} value {value}"));
+                }
+                engine.process.send("isready");
+            }
+            UciMessage::ReadyOk => engine.ready = true,
+            UciMessage::Info(info) => {
+                // Lines without moves only report progress, and further lines are alternatives.
+                if !info.pv.is_empty() && info.multipv.unwrap_or(1) == 1 {
+                    engine.last_info = Some(info);
+                }
+            }
+            UciMessage::BestMove(best) => {
+                engine.stopping = false;
+                let Some(search) = engine.searching.take() else {
+                    continue;
+                };
+                // The board changed while the engine was thinking, so the move is stale.
+                if search.kind != SearchKind::Play
+                    || search.fen != current_fen(&piece_query, &game_state)
+                {
+                    continue;
+                }
+                let board: Vec<(Piece, Square)> =
+                    piece_query.iter().map(|(p, s)| (*p, *s)).collect();
+                let legal = parse_uci_move(&best).filter(|&(start, end, promotion)| {
+                    board.iter().any(|(piece, square)| {
+                        (square.x, square.y) == start
+                            && piece.color == game_state.turn
+                            && is_legal_move(
+                                piece,
+                                start,
+                                end,
+                                &board,
+                                game_state.en_passant_target,
+                            )
+                            && is_promotion(piece, end) == promotion.is_some()
+                    })
+                });
+                match legal {
+                    Some((start, end, promotion)) => {
+                        commands.trigger(MoveRequestedEvent {
+                            start,
+                            end,
+                            promotion,
+                        });
+                    }
+                    None => {
+                        warn!("Engine sent the illegal move {best:?}, so it won't be asked again");
+                        engine.disabled = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn request_engine_move(
+    engine: Option<ResMut<UciEngine>>,
+    config: Res<UciConfig>,
+    players: Res<Players>,
+    piece_query: Query<(&Piece, &Square)>,
+    game_state: Res<GameState>,
+) {
+    let Some(mut engine) = engine else {
+        return;
+    };
+    if !engine.ready
+        || engine.disabled
+        || players.get(game_state.turn) != PlayerKind::Engine
+        || game_state.result.is_some()
+    {
+        return;
+    }
+
+    match &engine.searching {
+        // Playing takes priority over analysing.
+        Some(search) if search.kind == SearchKind::Analysis => engine.stop(),
+        Some(_) => {}
+        None => {
+            let fen = current_fen(&piece_query, &game_state);
+            engine.go(
+                fen,
+                SearchKind::Play,
+                &format!("movetime {}", config.movetime_ms),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_info_lines() {
+        let line = "info depth 12 seldepth 18 score cp -34 nodes 52000 pv e2e4 e7e5 g1f3";
+        let Some(UciMessage::Info(info)) = parse_uci_line(line) else {
+            panic!("not an info line");
+        };
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.seldepth, Some(18));
+        assert_eq!(info.score, Some(Score::Centipawns(-34)));
+        assert_eq!(info.nodes, Some(52000));
+        assert_eq!(info.pv, ["e2e4", "e7e5", "g1f3"]);
+
+        let Some(UciMessage::Info(info)) = parse_uci_line("info score mate -3 string pv a1a2")
+        else {
+            panic!("not an info line");
+        };
+        assert_eq!(info.score, Some(Score::Mate(-3)));
+        assert!(info.pv.is_empty());
+    }
+
+    #[test]
+    fn parses_best_moves_and_handshake() {
+        assert!(matches!(
+            parse_uci_line("bestmove e7e8q ponder a2a3"),
+            Some(UciMessage::BestMove(best)) if best == "e7e8q"
+        ));
+        assert!(matches!(
+            parse_uci_line("id name Some Engine 1.0"),
+            Some(UciMessage::Name(name)) if name == "Some Engine 1.0"
+        ));
+        assert!(matches!(parse_uci_line("uciok"), Some(UciMessage::UciOk)));
+        assert!(parse_uci_line("bestmove").is_none());
+        assert!(parse_uci_line("copyprotection ok").is_none());
+    }
+}
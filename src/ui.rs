@@ -1,31 +1,53 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, ui::RelativeCursorPosition, window::PrimaryWindow};
 
 use crate::{
-    components::{PieceColor, TurnText},
+    analysis::{AnalysisMode, Evaluation},
+    components::{
+        AnalysisText, DrawButton, HistoryList, LiveButton, NetStatusText, PieceColor, ResignButton,
+        SidePanel, TurnText,
+    },
     resources::GameState,
+    states::InGame,
+    uci::{Score, UciEngine},
 };
 
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_ui)
-            .add_systems(Update, update_turn_text);
+        app.add_systems(OnEnter(InGame), setup_ui).add_systems(
+            Update,
+            (update_turn_text, update_analysis_text, layout_side_panel).run_if(in_state(InGame)),
+        );
     }
 }
 
+// The panel takes a share of the window on the right, the board gets the rest.
+pub fn side_panel_width(window_width: f32) -> f32 {
+    (window_width * 0.3)
+        .clamp(320.0, 480.0)
+        .min(window_width / 2.0)
+}
+
+// Placed by `layout_side_panel` once the window size is known.
 fn setup_ui(mut commands: Commands) {
     commands
-        .spawn((Node {
-            width: Val::Percent(40.0),
-            height: Val::Percent(20.0),
-            justify_content: JustifyContent::Center,
-            align_items: AlignItems::Center,
-            position_type: PositionType::Absolute,
-            top: Val::Px(300.0),
-            left: Val::Px(1200.0),
-            ..default()
-        },))
+        .spawn((
+            Node {
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(20.0),
+                padding: UiRect::vertical(Val::Px(20.0)),
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.0),
+                right: Val::Px(0.0),
+                ..default()
+            },
+            SidePanel,
+            DespawnOnExit(InGame),
+        ))
         .with_children(|parent| {
             // Spawn text as a child of the container
             parent
@@ -38,15 +60,197 @@ fn setup_ui(mut commands: Commands) {
                     TextColor(Color::WHITE),
                 ))
                 .insert(TurnText);
+
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                TextLayout::new_with_justify(Justify::Center),
+                AnalysisText,
+            ));
+
+            // Filled in by the history plugin, one numbered pair of moves per row.
+            parent.spawn((
+                Node {
+                    width: Val::Px(260.0),
+                    height: Val::Px(360.0),
+                    flex_direction: FlexDirection::Row,
+                    flex_wrap: FlexWrap::Wrap,
+                    align_content: AlignContent::FlexStart,
+                    overflow: Overflow::scroll_y(),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                RelativeCursorPosition::default(),
+                HistoryList,
+            ));
+
+            parent.spawn((
+                Button,
+                Node {
+                    padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.3, 0.45, 0.25)),
+                Visibility::Hidden,
+                LiveButton,
+                children![(
+                    Text::new("Back to game"),
+                    TextFont {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                )],
+            ));
+
+            parent.spawn((
+                Button,
+                Node {
+                    padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.5, 0.2, 0.2)),
+                ResignButton,
+                children![(
+                    Text::new("Resign"),
+                    TextFont {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                )],
+            ));
+
+            // Only in games over the network, see the net plugin.
+            parent.spawn((
+                Button,
+                Node {
+                    padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.35, 0.35, 0.35)),
+                Visibility::Hidden,
+                DrawButton,
+                children![(
+                    Text::new("Offer draw"),
+                    TextFont {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                )],
+            ));
+
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 18.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.7, 0.7, 0.7)),
+                TextLayout::new_with_justify(Justify::Center),
+                NetStatusText,
+            ));
         });
 }
 
+fn layout_side_panel(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut panel_query: Query<&mut Node, With<SidePanel>>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+    let width = Val::Px(side_panel_width(window.width()));
+
+    for mut node in panel_query.iter_mut() {
+        if node.width != width {
+            node.width = width;
+        }
+    }
+}
+
 fn update_turn_text(game_state: Res<GameState>, mut text_query: Query<&mut Text, With<TurnText>>) {
     for mut text in text_query.iter_mut() {
-        let turn_str = match game_state.turn {
-            PieceColor::White => "White To Play",
-            PieceColor::Black => "Black To Play",
+        let turn_str = match (game_state.result, game_state.turn) {
+            (Some(result), _) => match result.winner {
+                Some(PieceColor::White) => "White Wins",
+                Some(PieceColor::Black) => "Black Wins",
+                None => "Draw",
+            },
+            (None, PieceColor::White) => "White To Play",
+            (None, PieceColor::Black) => "Black To Play",
         };
         **text = turn_str.to_string();
     }
 }
+
+fn update_analysis_text(
+    analysis: Res<AnalysisMode>,
+    evaluation: Res<Evaluation>,
+    engine: Option<Res<UciEngine>>,
+    mut text_query: Query<&mut Text, With<AnalysisText>>,
+) {
+    if !analysis.is_changed() && !evaluation.is_changed() {
+        return;
+    }
+
+    let analysis_str = if !analysis.enabled {
+        String::new()
+    } else if engine.is_none() {
+        "Analysis needs an engine (--engine <path>)".to_string()
+    } else {
+        let score = match evaluation.score {
+            Some(Score::Centipawns(cp)) => format!("{:+.2}", cp as f32 / 100.0),
+            Some(Score::Mate(moves)) => format!("#{moves}"),
+            None => "...".to_string(),
+        };
+        let depth = evaluation
+            .depth
+            .map(|depth| format!("  depth {depth}"))
+            .unwrap_or_default();
+        format!("{score}{depth}\n{}", evaluation.line)
+    };
+
+    for mut text in text_query.iter_mut() {
+        **text = analysis_str.clone();
+    }
+}
+
+// A plain button with a label, tagged with what it does.
+pub fn text_button(label: &str, action: impl Component) -> impl Bundle {
+    (
+        Button,
+        Node {
+            padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgb(0.3, 0.45, 0.25)),
+        action,
+        children![(
+            Text::new(label),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+        )],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_side_panel_leaves_room_for_the_board() {
+        assert_eq!(side_panel_width(1280.0), 384.0);
+        assert_eq!(side_panel_width(2560.0), 480.0);
+        assert_eq!(side_panel_width(800.0), 320.0);
+        assert_eq!(side_panel_width(500.0), 250.0);
+    }
+}